@@ -1,10 +1,17 @@
-#![cfg(feature = "ripe-geo-embedded")]
+#![cfg(feature = "ripe-geo")]
 use criterion::{black_box, criterion_group, Criterion};
+#[cfg(feature = "ripe-geo-embedded")]
 use geo302::geo::ripe_geo::RipeGeoImpl;
+use geo302::intervals::{IntervalBTreeMap, IntervalVec};
+#[cfg(feature = "ripe-geo-embedded")]
 use std::net::Ipv4Addr;
 
+#[cfg(feature = "ripe-geo-embedded")]
 criterion_group!(benches_interval_tree, bench_interval_trees);
 
+criterion_group!(benches_coalesce, bench_coalesce);
+
+#[cfg(feature = "ripe-geo-embedded")]
 pub fn bench_interval_trees(c: &mut Criterion) {
     let keys: [u32; 5] = [
         [0u8, 0, 0, 0],
@@ -66,3 +73,53 @@ pub fn bench_interval_trees(c: &mut Criterion) {
         });
     }
 }
+
+/// Pseudo-random numbers to make benchmark reproducible
+fn lcg(state: &mut u32) -> u32 {
+    *state = state.wrapping_mul(1664525).wrapping_add(1013904223);
+    *state >> 8
+}
+
+/// Adjacent /24 subnets with runs of equal values, like ripe-geo lists are
+fn adjacent_subnets() -> IntervalBTreeMap<u32, u8> {
+    const SUBNET_SIZE: u32 = 1 << 8;
+
+    let mut state = 0;
+    let mut btree_map = IntervalBTreeMap::new();
+    let mut value = 0;
+    let mut key = 1 << 24;
+    while btree_map.len() < 1 << 16 {
+        for _ in 0..=lcg(&mut state) % 16 {
+            btree_map.try_insert(key, SUBNET_SIZE, value).unwrap();
+            key += SUBNET_SIZE;
+        }
+        value = (value + 1 + (lcg(&mut state) % 5) as u8) % 6;
+    }
+    btree_map
+}
+
+pub fn bench_coalesce(c: &mut Criterion) {
+    let mut state = 1;
+    let keys = (0..64)
+        .map(|_| (1 << 24) + lcg(&mut state) % (1 << 24))
+        .collect::<Vec<u32>>();
+
+    let vecs: IntervalVec<_, _> = adjacent_subnets().into();
+    c.bench_function("IntervalVec::get adjacent", |b| {
+        b.iter(|| {
+            for &key in keys.iter() {
+                vecs.get(black_box(key));
+            }
+        })
+    });
+
+    let mut coalesced = vecs;
+    coalesced.coalesce();
+    c.bench_function("IntervalVec::get coalesced", |b| {
+        b.iter(|| {
+            for &key in keys.iter() {
+                coalesced.get(black_box(key));
+            }
+        })
+    });
+}
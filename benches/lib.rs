@@ -1,10 +1,16 @@
-#[cfg(feature = "ripe-geo-embedded")]
+#[cfg(feature = "ripe-geo")]
 use criterion::criterion_main;
 
 mod intervals;
 
 #[cfg(feature = "ripe-geo-embedded")]
-criterion_main!(intervals::benches_interval_tree);
+criterion_main!(
+    intervals::benches_interval_tree,
+    intervals::benches_coalesce
+);
+
+#[cfg(all(feature = "ripe-geo", not(feature = "ripe-geo-embedded")))]
+criterion_main!(intervals::benches_coalesce);
 
-#[cfg(not(feature = "ripe-geo-embedded"))]
+#[cfg(not(feature = "ripe-geo"))]
 fn main() {}
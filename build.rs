@@ -0,0 +1,34 @@
+use std::path::PathBuf;
+
+/// Resolve the ripe-geo snapshot to embed with `ripe-geo-embedded-snapshot` feature.
+/// Debug builds without GEO302_RIPE_GEO_SNAPSHOT embed the text lists instead, so
+/// `--all-features` builds work out of the box, but release builds must have the snapshot.
+fn main() {
+    println!("cargo:rerun-if-changed=build.rs");
+    println!("cargo:rerun-if-env-changed=GEO302_RIPE_GEO_SNAPSHOT");
+    println!("cargo:rustc-check-cfg=cfg(ripe_geo_embedded_snapshot)");
+    println!("cargo:rustc-check-cfg=cfg(ripe_geo_embedded_snapshot_missing)");
+
+    if std::env::var_os("CARGO_FEATURE_RIPE_GEO_EMBEDDED_SNAPSHOT").is_none() {
+        return;
+    }
+    match std::env::var_os("GEO302_RIPE_GEO_SNAPSHOT") {
+        Some(path) => {
+            let manifest_dir = std::env::var_os("CARGO_MANIFEST_DIR").unwrap();
+            // Relative paths are relative to the crate root
+            let path = PathBuf::from(manifest_dir).join(path);
+            println!("cargo:rerun-if-changed={}", path.display());
+            println!(
+                "cargo:rustc-env=GEO302_RIPE_GEO_SNAPSHOT_PATH={}",
+                path.display()
+            );
+            println!("cargo:rustc-cfg=ripe_geo_embedded_snapshot");
+        }
+        None if std::env::var("PROFILE").as_deref() == Ok("release") => {
+            println!("cargo:rustc-cfg=ripe_geo_embedded_snapshot_missing");
+        }
+        None => println!(
+            "cargo:warning=GEO302_RIPE_GEO_SNAPSHOT is not set, ripe-geo text lists are embedded instead of a snapshot"
+        ),
+    }
+}
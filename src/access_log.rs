@@ -0,0 +1,430 @@
+//! Access log separate from the diagnostic log
+use crate::geo::Continent;
+use crate::time_format::{clf_time, rfc3339};
+
+use hyper::body::HttpBody;
+use hyper::{header, Body, Request, Response};
+use serde::{Deserialize, Serialize};
+use std::fs::{File, OpenOptions};
+use std::io::{LineWriter, Write};
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime};
+use thiserror::Error;
+
+#[derive(Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+enum AccessLogFormatConfig {
+    /// Apache combined log format
+    #[default]
+    Combined,
+    /// JSON object per line
+    Json,
+    /// Custom template with `{field}` placeholders
+    Template,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct AccessLogConfig {
+    #[serde(default)]
+    format: AccessLogFormatConfig,
+    /// Required for "template" format
+    #[serde(default)]
+    template: Option<String>,
+    /// Log file, stdout is used if not specified
+    #[serde(default)]
+    path: Option<PathBuf>,
+}
+
+impl AccessLogConfig {
+    pub fn open(self) -> Result<AccessLog, AccessLogConfigError> {
+        let format = match (self.format, self.template) {
+            (AccessLogFormatConfig::Combined, None) => AccessLogFormat::Combined,
+            (AccessLogFormatConfig::Json, None) => AccessLogFormat::Json,
+            (AccessLogFormatConfig::Template, Some(template)) => {
+                AccessLogFormat::Template(parse_template(&template)?)
+            }
+            (AccessLogFormatConfig::Template, None) => {
+                return Err(AccessLogConfigError::TemplateMissing)
+            }
+            (_, Some(_)) => return Err(AccessLogConfigError::TemplateUnexpected),
+        };
+        let destination = match self.path {
+            Some(path) => {
+                let file = open_file(&path).map_err(|error| AccessLogConfigError::Open {
+                    path: path.clone(),
+                    error,
+                })?;
+                Destination::File { path, file }
+            }
+            None => Destination::Stdout,
+        };
+        Ok(AccessLog {
+            format,
+            destination: Mutex::new(destination),
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Field {
+    Time,
+    ClientIp,
+    SocketIp,
+    Continent,
+    Mirror,
+    Method,
+    Uri,
+    Protocol,
+    Status,
+    Location,
+    Bytes,
+    LatencyMs,
+    UserAgent,
+    Referer,
+    Host,
+}
+
+impl Field {
+    fn from_name(name: &str) -> Option<Self> {
+        Some(match name {
+            "time" => Self::Time,
+            "client_ip" => Self::ClientIp,
+            "socket_ip" => Self::SocketIp,
+            "continent" => Self::Continent,
+            "mirror" => Self::Mirror,
+            "method" => Self::Method,
+            "uri" => Self::Uri,
+            "protocol" => Self::Protocol,
+            "status" => Self::Status,
+            "location" => Self::Location,
+            "bytes" => Self::Bytes,
+            "latency_ms" => Self::LatencyMs,
+            "user_agent" => Self::UserAgent,
+            "referer" => Self::Referer,
+            "host" => Self::Host,
+            _ => return None,
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum TemplatePart {
+    Literal(String),
+    Field(Field),
+}
+
+fn parse_template(template: &str) -> Result<Vec<TemplatePart>, AccessLogConfigError> {
+    let mut parts = vec![];
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        if start > 0 {
+            parts.push(TemplatePart::Literal(rest[..start].to_owned()));
+        }
+        let end = rest[start..]
+            .find('}')
+            .ok_or_else(|| AccessLogConfigError::Template(template.to_owned()))?
+            + start;
+        let name = &rest[start + 1..end];
+        let field = Field::from_name(name)
+            .ok_or_else(|| AccessLogConfigError::TemplateField(name.to_owned()))?;
+        parts.push(TemplatePart::Field(field));
+        rest = &rest[end + 1..];
+    }
+    if !rest.is_empty() {
+        parts.push(TemplatePart::Literal(rest.to_owned()));
+    }
+    Ok(parts)
+}
+
+#[derive(Debug)]
+enum AccessLogFormat {
+    Combined,
+    Json,
+    Template(Vec<TemplatePart>),
+}
+
+#[derive(Debug)]
+enum Destination {
+    Stdout,
+    File {
+        path: PathBuf,
+        file: LineWriter<File>,
+    },
+}
+
+fn open_file(path: &Path) -> std::io::Result<LineWriter<File>> {
+    let file = OpenOptions::new().create(true).append(true).open(path)?;
+    Ok(LineWriter::new(file))
+}
+
+/// Details of the response which are not visible in the response itself, attached to the
+/// response as an extension
+#[derive(Debug, Clone, Default)]
+pub struct AccessInfo {
+    pub continent: Option<Continent>,
+    /// Name of the mirror the client is redirected to
+    pub mirror: Option<String>,
+}
+
+/// All fields of a single access log record
+#[derive(Serialize, Debug)]
+pub struct AccessRecord<'a> {
+    #[serde(serialize_with = "serialize_rfc3339")]
+    time: SystemTime,
+    /// Client IP resolved from proxy headers or the socket IP
+    client_ip: Option<IpAddr>,
+    /// Socket peer IP or source IP from PROXY protocol header, `None` for Unix domain sockets
+    socket_ip: Option<IpAddr>,
+    continent: Option<String>,
+    mirror: Option<&'a str>,
+    method: &'a str,
+    uri: String,
+    protocol: String,
+    status: u16,
+    location: Option<&'a str>,
+    bytes: Option<u64>,
+    latency_ms: f64,
+    user_agent: Option<&'a str>,
+    referer: Option<&'a str>,
+    host: Option<&'a str>,
+}
+
+impl<'a> AccessRecord<'a> {
+    pub fn new(
+        request: &'a Request<Body>,
+        response: &'a Response<Body>,
+        client_ip: Option<IpAddr>,
+        socket_ip: Option<IpAddr>,
+        latency: Duration,
+    ) -> Self {
+        let request_header = |name| {
+            request
+                .headers()
+                .get(name)
+                .and_then(|value| value.to_str().ok())
+        };
+        let access_info = response.extensions().get::<AccessInfo>();
+        Self {
+            time: SystemTime::now(),
+            client_ip,
+            socket_ip,
+            continent: access_info
+                .and_then(|info| info.continent)
+                .map(|continent| format!("{continent:?}")),
+            mirror: access_info.and_then(|info| info.mirror.as_deref()),
+            method: request.method().as_str(),
+            uri: request.uri().to_string(),
+            protocol: format!("{:?}", request.version()),
+            status: response.status().as_u16(),
+            location: response
+                .headers()
+                .get(header::LOCATION)
+                .and_then(|value| value.to_str().ok()),
+            bytes: response.body().size_hint().exact(),
+            latency_ms: latency.as_secs_f64() * 1e3,
+            user_agent: request_header(header::USER_AGENT),
+            referer: request_header(header::REFERER),
+            host: request_header(header::HOST),
+        }
+    }
+
+    fn field(&self, field: Field) -> String {
+        fn or_dash<T: ToString>(value: Option<T>) -> String {
+            value.map_or_else(|| "-".to_owned(), |value| value.to_string())
+        }
+
+        match field {
+            Field::Time => rfc3339(self.time),
+            Field::ClientIp => or_dash(self.client_ip),
+            Field::SocketIp => or_dash(self.socket_ip),
+            Field::Continent => or_dash(self.continent.as_ref()),
+            Field::Mirror => or_dash(self.mirror),
+            Field::Method => self.method.to_owned(),
+            Field::Uri => self.uri.clone(),
+            Field::Protocol => self.protocol.clone(),
+            Field::Status => self.status.to_string(),
+            Field::Location => or_dash(self.location),
+            Field::Bytes => or_dash(self.bytes),
+            Field::LatencyMs => format!("{:.3}", self.latency_ms),
+            Field::UserAgent => or_dash(self.user_agent),
+            Field::Referer => or_dash(self.referer),
+            Field::Host => or_dash(self.host),
+        }
+    }
+
+    fn combined(&self) -> String {
+        let quoted = |value: Option<&str>| value.unwrap_or("-").replace('"', "\\\"");
+        format!(
+            r#"{} - - [{}] "{} {} {}" {} {} "{}" "{}""#,
+            self.field(Field::ClientIp),
+            clf_time(self.time),
+            self.method,
+            self.uri,
+            self.protocol,
+            self.status,
+            self.field(Field::Bytes),
+            quoted(self.referer),
+            quoted(self.user_agent),
+        )
+    }
+
+    fn template(&self, parts: &[TemplatePart]) -> String {
+        parts.iter().fold(String::new(), |mut line, part| {
+            match part {
+                TemplatePart::Literal(literal) => line.push_str(literal),
+                TemplatePart::Field(field) => line.push_str(&self.field(*field)),
+            }
+            line
+        })
+    }
+}
+
+#[derive(Debug)]
+pub struct AccessLog {
+    format: AccessLogFormat,
+    destination: Mutex<Destination>,
+}
+
+impl AccessLog {
+    fn format(&self, record: &AccessRecord) -> String {
+        match &self.format {
+            AccessLogFormat::Combined => record.combined(),
+            AccessLogFormat::Json => serde_json::to_string(record).unwrap(),
+            AccessLogFormat::Template(parts) => record.template(parts),
+        }
+    }
+
+    pub fn write(&self, record: &AccessRecord) {
+        let line = self.format(record);
+        let result = match &mut *self.destination.lock().unwrap() {
+            Destination::Stdout => writeln!(std::io::stdout().lock(), "{line}"),
+            Destination::File { file, .. } => writeln!(file, "{line}"),
+        };
+        if let Err(e) = result {
+            log::error!("Cannot write access log: {e}");
+        }
+    }
+
+    /// Reopen access log file by its path, e.g. after it was moved by logrotate
+    pub fn reopen(&self) {
+        if let Destination::File { path, file } = &mut *self.destination.lock().unwrap() {
+            match open_file(path) {
+                Ok(new_file) => *file = new_file,
+                Err(e) => log::error!("Cannot reopen access log {path:?}: {e}"),
+            }
+        }
+    }
+}
+
+fn serialize_rfc3339<S: serde::Serializer>(time: &SystemTime, s: S) -> Result<S::Ok, S::Error> {
+    s.serialize_str(&rfc3339(*time))
+}
+
+#[derive(Error, Debug)]
+pub enum AccessLogConfigError {
+    #[error(r#"access_log.template is required for "template" format"#)]
+    TemplateMissing,
+    #[error(r#"access_log.template can be used with "template" format only"#)]
+    TemplateUnexpected,
+    #[error(r#"access log template "{0}" has unclosed "{{""#)]
+    Template(String),
+    #[error(r#"unknown access log template field "{0}""#)]
+    TemplateField(String),
+    #[error("cannot open access log {path:?}: {error}")]
+    Open {
+        path: PathBuf,
+        error: std::io::Error,
+    },
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::UNIX_EPOCH;
+
+    fn record<'a>(request: &'a Request<Body>, response: &'a Response<Body>) -> AccessRecord<'a> {
+        let mut record = AccessRecord::new(
+            request,
+            response,
+            Some("192.0.2.1".parse().unwrap()),
+            Some("10.0.0.1".parse().unwrap()),
+            Duration::from_micros(1500),
+        );
+        // 2000-10-10T13:55:36.123Z
+        record.time = UNIX_EPOCH + Duration::from_millis(971186136123);
+        record
+    }
+
+    fn request() -> Request<Body> {
+        Request::builder()
+            .uri("/ztf/file?a=1")
+            .header(header::HOST, "data.example.org")
+            .header(header::USER_AGENT, r#"curl "8""#)
+            .body(Body::empty())
+            .unwrap()
+    }
+
+    fn response() -> Response<Body> {
+        Response::builder()
+            .status(302)
+            .header(header::LOCATION, "https://a.example.org/ztf/file?a=1")
+            .extension(AccessInfo {
+                continent: Some(Continent::Europe),
+                mirror: Some("a".to_owned()),
+            })
+            .body(Body::empty())
+            .unwrap()
+    }
+
+    #[test]
+    fn combined() {
+        let (request, response) = (request(), response());
+        assert_eq!(
+            record(&request, &response).combined(),
+            r#"192.0.2.1 - - [10/Oct/2000:13:55:36 +0000] "GET /ztf/file?a=1 HTTP/1.1" 302 0 "-" "curl \"8\"""#
+        );
+    }
+
+    #[test]
+    fn json() {
+        let (request, response) = (request(), response());
+        let json: serde_json::Value = serde_json::to_string(&record(&request, &response))
+            .map(|s| serde_json::from_str(&s).unwrap())
+            .unwrap();
+        assert_eq!(json["time"], "2000-10-10T13:55:36.123Z");
+        assert_eq!(json["client_ip"], "192.0.2.1");
+        assert_eq!(json["socket_ip"], "10.0.0.1");
+        assert_eq!(json["continent"], "Europe");
+        assert_eq!(json["mirror"], "a");
+        assert_eq!(json["status"], 302);
+        assert_eq!(json["latency_ms"], 1.5);
+        assert_eq!(json["referer"], serde_json::Value::Null);
+    }
+
+    #[test]
+    fn template() {
+        let (request, response) = (request(), response());
+        let parts =
+            parse_template("{client_ip} via {socket_ip} {continent} -> {mirror} {latency_ms}ms")
+                .unwrap();
+        assert_eq!(
+            record(&request, &response).template(&parts),
+            "192.0.2.1 via 10.0.0.1 Europe -> a 1.500ms"
+        );
+    }
+
+    #[test]
+    fn invalid_template() {
+        assert!(matches!(
+            parse_template("{client_ip"),
+            Err(AccessLogConfigError::Template(_))
+        ));
+        assert!(matches!(
+            parse_template("{client}"),
+            Err(AccessLogConfigError::TemplateField(name)) if name == "client"
+        ));
+    }
+}
@@ -1,51 +1,96 @@
 #[cfg(feature = "multi-thread")]
 use geo302::config::ConfigThreads;
 use geo302::config::{parse_config, Config};
-use geo302::service::{log_response, make_error_response, Geo302Service, InvalidConfigError};
+#[cfg(feature = "ripe-geo")]
+use geo302::geo::ripe_geo::{RipeGeoImpl, RipeGeoOverlapsStrategy};
+use geo302::service::{Geo302Service, InvalidConfigError};
 
-use hyper::server::conn::AddrStream;
-use hyper::service::{make_service_fn, service_fn};
-use hyper::{Body, Request, Server};
-use std::convert::Infallible;
 use std::sync::Arc;
+use tokio::task::JoinSet;
 
-async fn async_main(config: Config) -> anyhow::Result<()> {
-    let host = config.host;
+async fn async_main(mut config: Config) -> anyhow::Result<()> {
+    let listener_configs = config.take_listeners()?;
 
-    simple_logger::init_with_level(config.log_level)?;
+    let logger = std::mem::take(&mut config.logging).init(config.log_level)?;
 
     let geo302_service = tokio::task::spawn_blocking(move || -> Result<_, InvalidConfigError> {
         Ok(Arc::new(Geo302Service::from_config(config)?))
     })
     .await??;
 
-    let make_service = make_service_fn(move |connection: &AddrStream| {
-        let socket_remote_ip = connection.remote_addr().ip();
+    #[cfg(unix)]
+    {
         let geo302_service = geo302_service.clone();
-        let service = service_fn(move |request: Request<Body>| {
-            let geo302_service = geo302_service.clone();
-            async move {
-                let response = geo302_service
-                    .response(socket_remote_ip, &request)
-                    .unwrap_or_else(make_error_response);
-                log_response(socket_remote_ip, &request, &response);
-                Ok::<_, Infallible>(response)
+        let mut sigusr1 =
+            tokio::signal::unix::signal(tokio::signal::unix::SignalKind::user_defined1())?;
+        tokio::spawn(async move {
+            while sigusr1.recv().await.is_some() {
+                log::info!("SIGUSR1 received, reopening log files");
+                logger.reopen();
+                geo302_service.reopen_access_log();
             }
         });
-        async move { Ok::<_, Infallible>(service) }
-    });
+    }
 
-    let server = Server::bind(&host).serve(make_service);
+    let mut listeners = Vec::with_capacity(listener_configs.len());
+    for listener_config in listener_configs {
+        listeners.push(listener_config.bind().await?);
+    }
 
-    if let Err(e) = server.await {
-        log::error!("server error: {}", e);
+    let mut servers = JoinSet::new();
+    for listener in listeners {
+        let geo302_service = geo302_service.clone();
+        servers.spawn(async move {
+            let address = listener.address().clone();
+            (address, listener.serve(geo302_service).await)
+        });
+    }
+    if let Some(result) = servers.join_next().await {
+        let (address, e) = result?;
+        log::error!("server error on {}: {}", address, e);
     }
     Err(anyhow::anyhow!("server exited"))
 }
 
+/// `geo302 db compile <CONTINENTS_DIR_OR_ARCHIVE> <OUTPUT> [--overlaps fail|skip]`
+#[cfg(feature = "ripe-geo")]
+fn db_command(args: &[String]) -> anyhow::Result<()> {
+    const USAGE: &str =
+        "Usage: geo302 db compile <CONTINENTS_DIR_OR_ARCHIVE> <OUTPUT> [--overlaps fail|skip]";
+
+    let (input, output, options) = match args {
+        [command, input, output, options @ ..] if command == "compile" => (input, output, options),
+        _ => anyhow::bail!(USAGE),
+    };
+    let overlaps_strategy = match options {
+        [] => RipeGeoOverlapsStrategy::default(),
+        [option, value] if option == "--overlaps" => match value.as_str() {
+            "fail" => RipeGeoOverlapsStrategy::Fail,
+            "skip" => RipeGeoOverlapsStrategy::Skip,
+            _ => anyhow::bail!(USAGE),
+        },
+        _ => anyhow::bail!(USAGE),
+    };
+
+    let ripe_geo_impl = RipeGeoImpl::from_path(input.as_ref(), overlaps_strategy)?;
+    let snapshot = ripe_geo_impl.to_snapshot();
+    std::fs::write(output, &snapshot)?;
+    println!(
+        "ripe-geo snapshot of {} bytes is written to {output}",
+        snapshot.len()
+    );
+    Ok(())
+}
+
 fn main() -> anyhow::Result<()> {
-    let config_path = std::env::args()
-        .nth(1)
+    let args: Vec<String> = std::env::args().collect();
+    #[cfg(feature = "ripe-geo")]
+    if args.get(1).map(String::as_str) == Some("db") {
+        return db_command(&args[2..]);
+    }
+    let config_path = args
+        .get(1)
+        .cloned()
         .unwrap_or_else(|| "geo302.toml".to_owned());
 
     let config = parse_config(&config_path)?;
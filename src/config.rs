@@ -1,8 +1,18 @@
+use crate::access_log::AccessLogConfig;
+use crate::fallback::FallbackConfig;
 use crate::geo::GeoConfig;
 use crate::healthcheck::HealthCheckConfig;
-use crate::mirror::Mirror;
-#[cfg(not(feature = "multi-thread"))]
+use crate::ip_network::IpNetwork;
+use crate::logging::LoggingConfig;
+use crate::mirror::{deserialize_mirrors, Mirror};
+use crate::redirect::RedirectStatus;
+use crate::routes::RouteConfig;
+use crate::server::ListenerConfig;
+#[cfg(feature = "tls")]
+pub(crate) use crate::tls::TlsConfig;
+#[cfg(not(all(feature = "multi-thread", feature = "tls")))]
 use crate::unavailable::Unavailable;
+use crate::virtual_host::VirtualHostConfig;
 
 use hyper::HeaderMap;
 use serde::Deserialize;
@@ -32,26 +42,70 @@ impl Default for ConfigThreads {
 #[cfg(not(feature = "multi-thread"))]
 type ConfigThreads = Unavailable;
 
+#[cfg(not(feature = "tls"))]
+pub(crate) type TlsConfig = Unavailable;
+
 #[derive(Deserialize, Debug)]
 #[serde(deny_unknown_fields)]
 pub struct Config {
-    #[serde(default = "Config::default_host")]
-    pub host: SocketAddr,
+    /// Shortcut for a single TCP listener, cannot be combined with `listeners`
+    #[serde(default)]
+    pub host: Option<SocketAddr>,
+    #[serde(default)]
+    pub tls: Option<TlsConfig>,
+    #[serde(default)]
+    pub listeners: Vec<ListenerConfig>,
     #[serde(default = "Config::default_ip_headers")]
     pub ip_headers: Vec<String>,
     #[serde(default = "Config::default_ip_headers_recursive")]
     pub ip_headers_recursive: bool,
+    /// If not empty, ip_headers are used only for connections from these networks and
+    /// ip_headers_recursive is ignored
+    #[serde(default)]
+    pub trusted_proxies: Vec<IpNetwork>,
     #[serde(default)]
     pub healthcheck: HealthCheckConfig,
     #[serde(default, with = "http_serde::header_map")]
     pub response_headers: HeaderMap,
+    #[serde(default)]
+    pub redirect_status: RedirectStatus,
+    /// Redirect status for paths starting with the given prefixes, the longest prefix wins
+    #[serde(default)]
+    pub redirect_status_paths: HashMap<String, RedirectStatus>,
+    /// Serve healthy mirror list at /.well-known/geo302/mirrors, it is opt-in because it exposes
+    /// mirror names and health-check errors
+    #[serde(default)]
+    pub mirror_list: bool,
+    /// Treat ".meta4" suffix of the path as a Metalink request, it is opt-in because it hides
+    /// real "*.meta4" files from clients which don't send Metalink `Accept` header
+    #[serde(default)]
+    pub metalink_suffix: bool,
+    /// What to do when no mirror is available
+    #[serde(default)]
+    pub fallback: FallbackConfig,
+    /// Dedicated access log, requests are logged with the diagnostic logger if not specified
+    #[serde(default)]
+    pub access_log: Option<AccessLogConfig>,
     #[serde(default = "Config::default_log_level")]
     pub log_level: log::Level,
+    /// Diagnostic log output and per-module levels
+    #[serde(default)]
+    pub logging: LoggingConfig,
     #[serde(default)]
     pub threads: ConfigThreads,
     pub geoip: GeoConfig,
+    #[serde(deserialize_with = "deserialize_mirrors")]
     pub mirrors: HashMap<String, Mirror>,
     pub continents: HashMap<String, Vec<String>>,
+    /// Continents to try in order when no mirror of the client's continent is available
+    #[serde(default)]
+    pub spillover: HashMap<String, Vec<String>>,
+    /// Per request path continent maps, top-level `continents` is used if no route matches
+    #[serde(default)]
+    pub routes: Vec<RouteConfig>,
+    /// Sites selected by "Host" header or SNI, top-level settings are used for other hosts
+    #[serde(default)]
+    pub virtual_hosts: Vec<VirtualHostConfig>,
 }
 
 impl Config {
@@ -59,6 +113,25 @@ impl Config {
         "127.0.0.1:8080".parse().unwrap()
     }
 
+    /// Take listener configurations out of the config, `host` and `tls` are converted to a single
+    /// listener if `listeners` is not specified
+    pub fn take_listeners(&mut self) -> Result<Vec<ListenerConfig>, ListenersConfigError> {
+        let host = self.host.take();
+        let tls = self.tls.take();
+        if self.listeners.is_empty() {
+            return Ok(vec![ListenerConfig {
+                address: host.unwrap_or_else(Self::default_host).into(),
+                tls,
+                mode: None,
+                proxy_protocol: None,
+            }]);
+        }
+        if host.is_some() || tls.is_some() {
+            return Err(ListenersConfigError::HostAndListeners);
+        }
+        Ok(std::mem::take(&mut self.listeners))
+    }
+
     fn default_ip_headers() -> Vec<String> {
         vec!["X-FORWARDED-FOR".into()]
     }
@@ -72,6 +145,12 @@ impl Config {
     }
 }
 
+#[derive(Error, Debug, PartialEq, Eq)]
+pub enum ListenersConfigError {
+    #[error(r#""host" and "tls" cannot be used together with "listeners""#)]
+    HostAndListeners,
+}
+
 #[derive(Error, Debug)]
 pub enum ConfigFileError {
     #[error(r#"Error reading file "{path}": {error}"#)]
@@ -110,18 +189,30 @@ mod tests {
 
     const CONFIG_EXAMPLES: Dir = include_dir!("$CARGO_MANIFEST_DIR/config-examples");
 
+    /// The gate lists every feature needed by the examples except "tls", so the test runs in
+    /// non-TLS builds too. Extend it when an example needs a new feature, or list the example
+    /// separately like TLS ones
     #[cfg(all(
         feature = "maxminddb",
+        feature = "maxminddb-autoupdate",
         feature = "ripe-geo-embedded",
         feature = "ripe-geo-autoupdate"
     ))]
     #[test]
     fn load_all_config_examples() {
+        // Examples with TLS listeners, they load only if "tls" feature is enabled
+        const TLS_CONFIG_EXAMPLES: &[&str] = &["proxy-protocol.toml", "tls.toml"];
+
         for entry in CONFIG_EXAMPLES.find("**/*.toml").unwrap() {
             let file = entry.as_file().unwrap();
             let toml_string = file.contents_utf8().unwrap();
             let result: Result<Config, _> = toml::from_str(toml_string);
-            assert!(result.is_ok(), "{result:?}");
+            let path = file.path().to_str().unwrap();
+            if cfg!(feature = "tls") || !TLS_CONFIG_EXAMPLES.contains(&path) {
+                assert!(result.is_ok(), "{path}: {result:?}");
+            } else {
+                assert!(result.is_err(), "{path} must fail without tls feature");
+            }
         }
     }
 
@@ -155,6 +246,54 @@ mod tests {
 
     load_config!(load_maxminddb_config, "maxmind-db.toml", "maxminddb");
 
+    load_config!(
+        load_maxminddb_autoupdate_config,
+        "maxmind-db-autoupdate.toml",
+        "maxminddb-autoupdate"
+    );
+
+    load_config!(load_tls_config, "tls.toml", "maxminddb", "tls");
+
+    load_config!(load_listeners_config, "listeners.toml", "maxminddb");
+
+    load_config!(load_routes_config, "routes.toml", "maxminddb");
+
+    load_config!(load_virtual_hosts_config, "virtual-hosts.toml", "maxminddb");
+
+    load_config!(load_rewrite_config, "rewrite.toml", "maxminddb");
+
+    load_config!(load_access_log_config, "access-log.toml", "maxminddb");
+
+    load_config!(
+        load_proxy_protocol_config,
+        "proxy-protocol.toml",
+        "maxminddb",
+        "tls"
+    );
+
+    #[cfg(feature = "maxminddb")]
+    #[test]
+    fn take_listeners_from_host() {
+        let mut config = load_from_example_config("maxmind-db.toml").unwrap();
+        let listeners = config.take_listeners().unwrap();
+        assert_eq!(listeners.len(), 1);
+        assert_eq!(
+            listeners[0].address,
+            "0.0.0.0:8000".parse::<SocketAddr>().unwrap().into()
+        );
+    }
+
+    #[cfg(feature = "maxminddb")]
+    #[test]
+    fn take_listeners_host_and_listeners() {
+        let mut config = load_from_example_config("listeners.toml").unwrap();
+        config.host = Some(Config::default_host());
+        assert_eq!(
+            config.take_listeners().unwrap_err(),
+            ListenersConfigError::HostAndListeners
+        );
+    }
+
     load_config!(
         load_ripe_geo_autoupdate_no_dir_1,
         "ripe-geo-autoupdate-no-dir-1.toml",
@@ -175,6 +314,21 @@ mod tests {
         "ripe-geo-autoupdate-no-dir-4.toml",
         "ripe-geo-autoupdate"
     );
+    load_config!(
+        load_ripe_geo_autoupdate_cache_dir,
+        "ripe-geo-autoupdate-cache-dir.toml",
+        "ripe-geo-autoupdate"
+    );
+    load_config!(
+        load_ripe_geo_autoupdate_verification,
+        "ripe-geo-autoupdate-verification.toml",
+        "ripe-geo-autoupdate"
+    );
+    load_config!(
+        load_ripe_geo_autoupdate_local,
+        "ripe-geo-autoupdate-local.toml",
+        "ripe-geo-autoupdate"
+    );
 
     // Negative test doesn't work well here
     #[cfg(feature = "ripe-geo-embedded")]
@@ -222,6 +376,11 @@ mod tests {
         "ripe-geo-from-dir-and-autoupdate-5.toml",
         "ripe-geo-autoupdate"
     );
+    load_config!(
+        load_ripe_geo_from_dir_and_autoupdate_6,
+        "ripe-geo-from-dir-and-autoupdate-6.toml",
+        "ripe-geo-autoupdate"
+    );
 
     load_config!(
         load_ripe_geo_from_dir_no_autoupdate_1,
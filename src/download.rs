@@ -0,0 +1,255 @@
+use hyper::body::{Body, Bytes, HttpBody};
+use hyper::client::connect::Connect;
+use hyper::client::Client;
+use hyper::header::{self, HeaderMap, HeaderValue};
+use hyper::http::{request, uri::Uri};
+use hyper::StatusCode;
+use std::io::Write;
+use std::path::Path;
+use std::time::SystemTime;
+use thiserror::Error;
+
+/// Maximum number of requests following redirects
+const MAX_ATTEMPTS: usize = 8;
+
+#[derive(Debug, Error)]
+pub enum DownloadError {
+    #[error(transparent)]
+    Hyper(#[from] hyper::Error),
+    #[error(transparent)]
+    Http(#[from] hyper::http::Error),
+    #[error("Non-success status code: {0}")]
+    NonSuccess(StatusCode),
+    #[error("Download is larger than {0} bytes")]
+    TooLarge(u64),
+}
+
+impl From<StatusCode> for DownloadError {
+    fn from(status_code: StatusCode) -> Self {
+        DownloadError::NonSuccess(status_code)
+    }
+}
+
+/// Validators of the last download, used to make conditional requests
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct HttpValidators {
+    pub etag: Option<HeaderValue>,
+    pub last_modified: Option<HeaderValue>,
+}
+
+impl HttpValidators {
+    fn from_headers(headers: &HeaderMap) -> Self {
+        Self {
+            etag: headers.get(header::ETAG).cloned(),
+            last_modified: headers.get(header::LAST_MODIFIED).cloned(),
+        }
+    }
+
+    fn add_headers(&self, mut builder: request::Builder) -> request::Builder {
+        if let Some(etag) = &self.etag {
+            builder = builder.header(header::IF_NONE_MATCH, etag);
+        }
+        if let Some(last_modified) = &self.last_modified {
+            builder = builder.header(header::IF_MODIFIED_SINCE, last_modified);
+        }
+        builder
+    }
+}
+
+/// Download `uri` following redirects, `None` is returned if it is not modified since the
+/// download `validators` are taken from. `authorization` is sent to the host of `uri` only, so
+/// pre-signed storage URLs of redirects don't get credentials
+pub async fn fetch<C>(
+    client: &Client<C>,
+    uri: &Uri,
+    authorization: Option<&HeaderValue>,
+    validators: &HttpValidators,
+    max_size: u64,
+) -> Result<Option<(Bytes, HttpValidators)>, DownloadError>
+where
+    C: Connect + Clone + Send + Sync + 'static,
+{
+    let mut current_uri = uri.clone();
+    let mut attempt = 0;
+    let response = loop {
+        let mut builder = validators.add_headers(hyper::Request::builder().uri(&current_uri));
+        if let Some(authorization) = authorization {
+            if current_uri.authority() == uri.authority() {
+                builder = builder.header(header::AUTHORIZATION, authorization);
+            }
+        }
+        let response = client.request(builder.body(Body::empty())?).await?;
+
+        if response.status().is_success() {
+            break response;
+        } else if response.status() == StatusCode::NOT_MODIFIED {
+            return Ok(None);
+        } else if response.status().is_redirection() {
+            current_uri = response
+                .headers()
+                .get(header::LOCATION)
+                .ok_or_else(|| response.status())?
+                .as_bytes()
+                .try_into()
+                .map_err(|_| response.status())?;
+        } else {
+            return Err(response.status().into());
+        }
+
+        attempt += 1;
+        if attempt == MAX_ATTEMPTS {
+            return Err(response.status().into());
+        }
+    };
+    let new_validators = HttpValidators::from_headers(response.headers());
+    let content_length = response
+        .headers()
+        .get(header::CONTENT_LENGTH)
+        .and_then(|value| value.to_str().ok()?.parse::<u64>().ok());
+    if content_length.map_or(false, |length| length > max_size) {
+        return Err(DownloadError::TooLarge(max_size));
+    }
+    let mut body = response.into_body();
+    let mut content = Vec::with_capacity(content_length.unwrap_or(0) as usize);
+    while let Some(chunk) = body.data().await {
+        let chunk = chunk?;
+        if (content.len() + chunk.len()) as u64 > max_size {
+            return Err(DownloadError::TooLarge(max_size));
+        }
+        content.extend_from_slice(&chunk);
+    }
+    Ok(Some((content.into(), new_validators)))
+}
+
+/// Write `content` to a temporary file in the same directory and rename it, so the file is never
+/// partially written and memory-mapped readers of the old file are not affected
+pub fn write_atomically(path: &Path, content: &[u8]) -> std::io::Result<()> {
+    let mut tmp_name = std::ffi::OsString::from(".");
+    tmp_name.push(path.file_name().unwrap_or_default());
+    tmp_name.push(".tmp");
+    let tmp_path = path.with_file_name(tmp_name);
+    {
+        let mut file = std::fs::File::create(&tmp_path)?;
+        file.write_all(content)?;
+        file.sync_all()?;
+    }
+    std::fs::rename(&tmp_path, path)
+}
+
+/// Modification time and size of a local file or directory, used to skip unchanged ones
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FileStamp {
+    /// The latest modification time of the file or of the directory and its files
+    modified: SystemTime,
+    /// Total size of the files
+    size: u64,
+    /// Number of the files, so removed files are noticed
+    files: usize,
+}
+
+impl FileStamp {
+    pub fn new(path: &Path) -> std::io::Result<Self> {
+        let metadata = std::fs::metadata(path)?;
+        let mut stamp = Self {
+            modified: metadata.modified()?,
+            size: 0,
+            files: 0,
+        };
+        if metadata.is_dir() {
+            for entry in std::fs::read_dir(path)? {
+                let metadata = entry?.metadata()?;
+                if metadata.is_file() {
+                    stamp.add(&metadata)?;
+                }
+            }
+        } else {
+            stamp.add(&metadata)?;
+        }
+        Ok(stamp)
+    }
+
+    /// Total size of the files
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+
+    fn add(&mut self, metadata: &std::fs::Metadata) -> std::io::Result<()> {
+        self.modified = self.modified.max(metadata.modified()?);
+        self.size += metadata.len();
+        self.files += 1;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+
+    /// Serve `body` at `path` with ETag "v1", honoring If-None-Match, return the server URI
+    pub async fn serve(body: Vec<u8>, path: &str) -> Uri {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        let body = Bytes::from(body);
+        tokio::spawn(async move {
+            loop {
+                let (stream, _) = listener.accept().await.unwrap();
+                let body = body.clone();
+                let service = hyper::service::service_fn(move |request: hyper::Request<Body>| {
+                    let response = if request.headers().get(header::IF_NONE_MATCH)
+                        == Some(&HeaderValue::from_static("\"v1\""))
+                    {
+                        hyper::Response::builder()
+                            .status(StatusCode::NOT_MODIFIED)
+                            .body(Body::empty())
+                    } else {
+                        hyper::Response::builder()
+                            .header(header::ETAG, "\"v1\"")
+                            .body(body.clone().into())
+                    };
+                    async move { response }
+                });
+                tokio::spawn(hyper::server::conn::Http::new().serve_connection(stream, service));
+            }
+        });
+        format!("http://{address}/{path}").parse().unwrap()
+    }
+
+    #[tokio::test]
+    async fn conditional_fetch() {
+        let uri = serve(b"content".to_vec(), "file").await;
+        let client = Client::new();
+        let (content, validators) = fetch(&client, &uri, None, &Default::default(), 1024)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(content, "content");
+        assert_eq!(validators.etag.as_ref().unwrap(), "\"v1\"");
+        assert!(fetch(&client, &uri, None, &validators, 1024)
+            .await
+            .unwrap()
+            .is_none());
+        assert!(matches!(
+            fetch(&client, &uri, None, &Default::default(), 6).await,
+            Err(DownloadError::TooLarge(6))
+        ));
+    }
+
+    #[test]
+    fn atomic_write_and_stamp() {
+        let dir = std::env::temp_dir().join(format!("geo302-download-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("file");
+        write_atomically(&path, b"first").unwrap();
+        let stamp = FileStamp::new(&path).unwrap();
+        assert_eq!(stamp, FileStamp::new(&path).unwrap());
+        // Modification time resolution may be too coarse to notice the change, the size is not
+        write_atomically(&path, b"second").unwrap();
+        let new_stamp = FileStamp::new(&path).unwrap();
+        let content = std::fs::read(&path).unwrap();
+        let tmp_exists = dir.join(".file.tmp").exists();
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert_ne!(new_stamp, stamp);
+        assert_eq!(content, b"second");
+        assert!(!tmp_exists);
+    }
+}
@@ -0,0 +1,201 @@
+use crate::mirror::Mirror;
+use crate::non_zero_duration::NonZeroDuration;
+
+use hyper::body::Bytes;
+use hyper::header::{self, HeaderValue, InvalidHeaderValue};
+use hyper::{Body, Response, StatusCode};
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use thiserror::Error;
+
+/// What to do when no mirror is available
+#[derive(Deserialize, Debug, Default)]
+#[serde(deny_unknown_fields)]
+pub struct FallbackConfig {
+    /// Mirror to redirect to when no mirror is available, regardless of its health
+    #[serde(default)]
+    origin: Option<String>,
+    /// File to respond with instead of the default error message
+    #[serde(default)]
+    error_page: Option<PathBuf>,
+    /// Content type of error_page, guessed by the file extension if not specified
+    #[serde(default)]
+    error_page_content_type: Option<String>,
+    /// Value of Retry-After header of 503 responses, in seconds
+    #[serde(default)]
+    retry_after: Option<NonZeroDuration>,
+}
+
+#[derive(Debug)]
+struct ErrorPage {
+    content_type: HeaderValue,
+    body: Bytes,
+}
+
+#[derive(Debug, Default)]
+pub struct Fallback {
+    pub origin: Option<Mirror>,
+    error_page: Option<ErrorPage>,
+    retry_after: Option<HeaderValue>,
+}
+
+impl Fallback {
+    pub fn from_config(
+        config: FallbackConfig,
+        mirrors: &HashMap<String, Mirror>,
+    ) -> Result<Self, FallbackConfigError> {
+        let origin = config
+            .origin
+            .map(|name| {
+                mirrors
+                    .get(&name)
+                    .cloned()
+                    .ok_or(FallbackConfigError::OriginUnknown(name))
+            })
+            .transpose()?;
+        let error_page = config
+            .error_page
+            .map(|path| {
+                let body =
+                    std::fs::read(&path).map_err(|error| FallbackConfigError::ErrorPage {
+                        path: path.clone(),
+                        error,
+                    })?;
+                let content_type = match config.error_page_content_type {
+                    Some(content_type) => content_type.try_into()?,
+                    None => HeaderValue::from_static(Self::guess_content_type(&path)),
+                };
+                Ok::<_, FallbackConfigError>(ErrorPage {
+                    content_type,
+                    body: body.into(),
+                })
+            })
+            .transpose()?;
+        let retry_after = config.retry_after.map(|retry_after| {
+            Duration::from(retry_after)
+                .as_secs()
+                .to_string()
+                .try_into()
+                .unwrap()
+        });
+        Ok(Self {
+            origin,
+            error_page,
+            retry_after,
+        })
+    }
+
+    fn guess_content_type(path: &Path) -> &'static str {
+        match path.extension().and_then(|extension| extension.to_str()) {
+            Some("html" | "htm") => "text/html; charset=utf-8",
+            Some("json") => "application/json",
+            _ => "text/plain; charset=utf-8",
+        }
+    }
+
+    /// 503 response with the custom error page and Retry-After header if configured
+    pub fn unavailable_response(&self, message: String) -> Response<Body> {
+        let mut builder = Response::builder().status(StatusCode::SERVICE_UNAVAILABLE);
+        if let Some(retry_after) = &self.retry_after {
+            builder = builder.header(header::RETRY_AFTER, retry_after);
+        }
+        let (content_type, body) = match &self.error_page {
+            Some(ErrorPage { content_type, body }) => (content_type.clone(), body.clone().into()),
+            None => (
+                HeaderValue::from_static("text/plain; charset=utf-8"),
+                message.into(),
+            ),
+        };
+        builder
+            .header(header::CONTENT_TYPE, content_type)
+            .body(body)
+            .unwrap()
+    }
+}
+
+#[derive(Error, Debug)]
+pub enum FallbackConfigError {
+    #[error(r#"fallback origin "{0}" is not in mirrors"#)]
+    OriginUnknown(String),
+    #[error("cannot read fallback error page {path:?}: {error}")]
+    ErrorPage {
+        path: PathBuf,
+        error: std::io::Error,
+    },
+    #[error("invalid fallback error page content type: {0}")]
+    ContentType(#[from] InvalidHeaderValue),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Deserialize)]
+    struct Config {
+        #[serde(deserialize_with = "crate::mirror::deserialize_mirrors")]
+        mirrors: HashMap<String, Mirror>,
+        fallback: FallbackConfig,
+    }
+
+    fn fallback_from_str(s: &str) -> Result<Fallback, FallbackConfigError> {
+        let config: Config = toml::from_str(s).unwrap();
+        Fallback::from_config(config.fallback, &config.mirrors)
+    }
+
+    const MIRRORS: &str = r#"
+        [mirrors]
+        a = { upstream = "http://a.example.com", healthcheck = "http://a.example.com/ping" }
+        "#;
+
+    #[test]
+    fn default_response() {
+        let fallback = fallback_from_str(&format!("{MIRRORS}\n[fallback]")).unwrap();
+        assert!(fallback.origin.is_none());
+        let response = fallback.unavailable_response("No available mirrors".to_owned());
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert!(response.headers().get(header::RETRY_AFTER).is_none());
+        assert_eq!(
+            response.headers()[header::CONTENT_TYPE],
+            "text/plain; charset=utf-8"
+        );
+    }
+
+    #[test]
+    fn origin() {
+        let fallback =
+            fallback_from_str(&format!("{MIRRORS}\n[fallback]\norigin = \"a\"")).unwrap();
+        assert_eq!(fallback.origin.unwrap().name, "a");
+    }
+
+    #[test]
+    fn retry_after_header() {
+        let fallback =
+            fallback_from_str(&format!("{MIRRORS}\n[fallback]\nretry_after = 30")).unwrap();
+        let response = fallback.unavailable_response(String::new());
+        assert_eq!(response.headers()[header::RETRY_AFTER], "30");
+    }
+
+    #[test]
+    fn unknown_origin() {
+        assert!(matches!(
+            fallback_from_str(&format!("{MIRRORS}\n[fallback]\norigin = \"b\"")),
+            Err(FallbackConfigError::OriginUnknown(name)) if name == "b"
+        ));
+    }
+
+    #[test]
+    fn error_page() {
+        let path =
+            std::env::temp_dir().join(format!("geo302-error-page-{}.json", std::process::id()));
+        std::fs::write(&path, r#"{"error": "unavailable"}"#).unwrap();
+        let fallback = fallback_from_str(&format!(
+            "{MIRRORS}\n[fallback]\nerror_page = {:?}",
+            path.to_str().unwrap()
+        ));
+        std::fs::remove_file(&path).unwrap();
+        let response = fallback.unwrap().unavailable_response(String::new());
+        assert_eq!(response.headers()[header::CONTENT_TYPE], "application/json");
+    }
+}
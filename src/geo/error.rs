@@ -1,6 +1,8 @@
 #[cfg(feature = "ripe-geo")]
 use crate::geo::ripe_geo::RipeGeoDataError;
 
+#[cfg(feature = "maxminddb-autoupdate")]
+use crate::geo::max_mind_db::updater::MaxMindDbUpdateError;
 #[cfg(feature = "maxminddb")]
 use maxminddb::MaxMindDBError;
 use thiserror::Error;
@@ -12,6 +14,9 @@ pub enum GeoError {
     #[cfg(feature = "maxminddb")]
     #[error(transparent)]
     MaxMindDBError(#[from] MaxMindDBError),
+    #[cfg(feature = "maxminddb-autoupdate")]
+    #[error("Cannot download MaxMind DB: {0}")]
+    MaxMindDbDownload(#[from] MaxMindDbUpdateError),
     #[cfg(feature = "ripe-geo")]
     #[error(transparent)]
     RipeGeo(#[from] RipeGeoDataError),
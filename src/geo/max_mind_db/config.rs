@@ -0,0 +1,94 @@
+#[cfg(feature = "maxminddb-autoupdate")]
+use super::updater::MaxMindDbUpdater;
+use super::*;
+#[cfg(feature = "maxminddb-autoupdate")]
+use crate::download::{FileStamp, HttpValidators};
+#[cfg(not(feature = "maxminddb-autoupdate"))]
+use crate::unavailable::Unavailable;
+
+use serde::Deserialize;
+use std::path::PathBuf;
+
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct MaxMindDbConfig {
+    path: PathBuf,
+    /// Memory-map the file instead of reading it into memory
+    #[serde(default)]
+    mmap: bool,
+    #[serde(default)]
+    autoupdate: MaxMindDbAutoupdateConfig,
+}
+
+impl TryInto<MaxMindDbGeo> for MaxMindDbConfig {
+    type Error = GeoError;
+
+    fn try_into(self) -> Result<MaxMindDbGeo, Self::Error> {
+        let Self {
+            path,
+            mmap,
+            autoupdate,
+        } = self;
+        #[cfg(feature = "maxminddb-autoupdate")]
+        let updater = autoupdate
+            .into_updater()
+            .map(|updater| updater.with_path(path.clone()).with_mmap(mmap));
+        #[cfg(feature = "maxminddb-autoupdate")]
+        let mut validators = HttpValidators::default();
+        #[cfg(feature = "maxminddb-autoupdate")]
+        if let Some(updater) = &updater {
+            if updater.downloads() && !path.exists() {
+                validators = updater.download_blocking(None)?;
+                log::info!("MaxMind DB is downloaded to {path:?}");
+            }
+        }
+        // Take the stamp before loading, so changes made while loading are not missed
+        #[cfg(feature = "maxminddb-autoupdate")]
+        let stamp = FileStamp::new(&path).ok();
+
+        #[allow(unused_mut)]
+        let mut geo = MaxMindDbGeo::open(&path, mmap)?;
+        if mmap {
+            log::info!("Maxmind DB is memory-mapped from {path:?}");
+        } else {
+            log::info!("Maxmind DB is loaded from {path:?}");
+        }
+
+        #[cfg(feature = "maxminddb-autoupdate")]
+        geo.set_updater(
+            updater.map(|updater| updater.with_stamp(stamp).with_validators(validators)),
+        );
+        #[cfg(not(feature = "maxminddb-autoupdate"))]
+        let _ = autoupdate;
+        Ok(geo)
+    }
+}
+
+#[cfg(feature = "maxminddb-autoupdate")]
+#[derive(Deserialize, Debug)]
+#[serde(untagged)]
+enum MaxMindDbAutoupdateConfig {
+    Boolean(bool),
+    Updater(Box<MaxMindDbUpdater>),
+}
+
+#[cfg(feature = "maxminddb-autoupdate")]
+impl Default for MaxMindDbAutoupdateConfig {
+    fn default() -> Self {
+        Self::Boolean(false)
+    }
+}
+
+#[cfg(feature = "maxminddb-autoupdate")]
+impl MaxMindDbAutoupdateConfig {
+    fn into_updater(self) -> Option<MaxMindDbUpdater> {
+        match self {
+            Self::Boolean(false) => None,
+            Self::Boolean(true) => Some(MaxMindDbUpdater::default()),
+            Self::Updater(updater) => Some(*updater),
+        }
+    }
+}
+
+#[cfg(not(feature = "maxminddb-autoupdate"))]
+type MaxMindDbAutoupdateConfig = Unavailable;
@@ -0,0 +1,153 @@
+use crate::geo::{Continent, GeoError, GeoTrait};
+
+use maxminddb::geoip2;
+use std::net::IpAddr;
+use std::path::Path;
+#[cfg(feature = "maxminddb-autoupdate")]
+use std::sync::{Arc, RwLock};
+
+pub mod config;
+#[cfg(feature = "maxminddb-autoupdate")]
+pub mod updater;
+
+struct GeoNameId(pub u32);
+
+impl From<u32> for GeoNameId {
+    fn from(v: u32) -> Self {
+        Self(v)
+    }
+}
+
+impl TryInto<Continent> for GeoNameId {
+    type Error = GeoError;
+
+    fn try_into(self) -> Result<Continent, GeoError> {
+        match self.0 {
+            6255146_u32 => Ok(Continent::Africa),
+            6255147_u32 => Ok(Continent::Asia),
+            6255148_u32 => Ok(Continent::Europe),
+            6255149_u32 => Ok(Continent::NorthAmerica),
+            6255151_u32 => Ok(Continent::Oceania),
+            6255150_u32 => Ok(Continent::SouthAmerica),
+            6255152_u32 => Ok(Continent::Antarctica),
+            _ => Err(GeoError::ContinentUnknown),
+        }
+    }
+}
+
+/// Database content, either read into memory or memory-mapped
+pub enum MaxMindDbSource {
+    Heap(Vec<u8>),
+    /// Pages are shared between processes using the same file and are loaded on demand
+    Mmap(memmap2::Mmap),
+}
+
+impl AsRef<[u8]> for MaxMindDbSource {
+    fn as_ref(&self) -> &[u8] {
+        match self {
+            Self::Heap(vec) => vec,
+            Self::Mmap(mmap) => mmap,
+        }
+    }
+}
+
+type MaxMindDbReader = maxminddb::Reader<MaxMindDbSource>;
+
+fn open_reader(path: &Path, mmap: bool) -> Result<MaxMindDbReader, maxminddb::MaxMindDBError> {
+    let source = if mmap {
+        let file = std::fs::File::open(path)?;
+        // SAFETY: the file must not be modified in place while it is mapped, so it must be
+        // replaced by renaming, as both geoipupdate and the autoupdate do
+        MaxMindDbSource::Mmap(unsafe { memmap2::Mmap::map(&file) }?)
+    } else {
+        MaxMindDbSource::Heap(std::fs::read(path)?)
+    };
+    maxminddb::Reader::from_source(source)
+}
+
+#[cfg(feature = "maxminddb-autoupdate")]
+pub struct MaxMindDbGeo {
+    maxminddb_reader: Arc<RwLock<MaxMindDbReader>>,
+    updater: Option<Box<RwLock<updater::MaxMindDbUpdater>>>,
+}
+
+#[cfg(not(feature = "maxminddb-autoupdate"))]
+pub struct MaxMindDbGeo {
+    maxminddb_reader: MaxMindDbReader,
+}
+
+impl From<MaxMindDbReader> for MaxMindDbGeo {
+    fn from(maxminddb_reader: MaxMindDbReader) -> Self {
+        #[cfg(feature = "maxminddb-autoupdate")]
+        {
+            Self {
+                maxminddb_reader: Arc::new(RwLock::new(maxminddb_reader)),
+                updater: None,
+            }
+        }
+        #[cfg(not(feature = "maxminddb-autoupdate"))]
+        {
+            Self { maxminddb_reader }
+        }
+    }
+}
+
+impl MaxMindDbGeo {
+    pub fn from_file<P: AsRef<Path>>(filepath: P) -> Result<Self, GeoError> {
+        Self::open(filepath, false)
+    }
+
+    /// Read the file into memory or memory-map it
+    pub fn open<P: AsRef<Path>>(filepath: P, mmap: bool) -> Result<Self, GeoError> {
+        Ok(open_reader(filepath.as_ref(), mmap)?.into())
+    }
+
+    fn lookup_continent(
+        maxminddb_reader: &MaxMindDbReader,
+        address: IpAddr,
+    ) -> Result<Continent, GeoError> {
+        // map_err could be replaced with inspect_err when it is stable
+        // https://github.com/rust-lang/rust/issues/91345
+        let country: geoip2::Country = maxminddb_reader.lookup(address).map_err(|err| {
+            log::warn!("{:?}", err);
+            err
+        })?;
+        let geo_name_id: GeoNameId = country
+            .continent
+            .ok_or(GeoError::ContinentUnknown)?
+            .geoname_id
+            .ok_or(GeoError::ContinentUnknown)?
+            .into();
+        geo_name_id.try_into()
+    }
+}
+
+impl GeoTrait for MaxMindDbGeo {
+    fn try_lookup_continent(&self, address: IpAddr) -> Result<Continent, GeoError> {
+        #[cfg(feature = "maxminddb-autoupdate")]
+        {
+            Self::lookup_continent(&self.maxminddb_reader.read().unwrap(), address)
+        }
+        #[cfg(not(feature = "maxminddb-autoupdate"))]
+        {
+            Self::lookup_continent(&self.maxminddb_reader, address)
+        }
+    }
+
+    fn start_autoupdate(&self) -> bool {
+        #[cfg(feature = "maxminddb-autoupdate")]
+        {
+            let mut updater = match &self.updater {
+                Some(value) => value,
+                None => return false,
+            }
+            .write()
+            .unwrap();
+            updater.start(self).is_some()
+        }
+        #[cfg(not(feature = "maxminddb-autoupdate"))]
+        {
+            false
+        }
+    }
+}
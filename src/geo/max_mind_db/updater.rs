@@ -0,0 +1,673 @@
+use super::*;
+
+use crate::download::{fetch, write_atomically, DownloadError, FileStamp, HttpValidators};
+use crate::non_zero_duration::NonZeroDuration;
+
+use base64::Engine;
+use hyper::body::Body;
+use hyper::client::connect::Connect;
+use hyper::client::Client;
+use hyper::header::HeaderValue;
+use hyper::http::uri::Uri;
+use hyper::StatusCode;
+use hyper_tls::HttpsConnector;
+use maxminddb::MaxMindDBError;
+use serde::Deserialize;
+use std::io::Read;
+use std::path::PathBuf;
+use std::time::Duration;
+use thiserror::Error;
+use tokio::runtime::Handle;
+
+const MAXMIND_DB_UPDATE_INTERVAL_SECONDS: u64 = 3600;
+
+const MAXMIND_DB_MAX_SIZE: u64 = 256 << 20;
+
+const MAXMIND_DB_DOWNLOAD_TIMEOUT_SECONDS: u64 = 600;
+
+const MAXMIND_DB_DEFAULT_EDITION_ID: &str = "GeoLite2-Country";
+
+#[derive(Debug, Error)]
+pub enum MaxMindDbUpdateError {
+    #[error(transparent)]
+    Hyper(#[from] hyper::Error),
+    #[error(transparent)]
+    Http(#[from] hyper::http::Error),
+    #[error("Non-success status code: {0}")]
+    NonSuccess(StatusCode),
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+    #[error("Download is larger than {0} bytes")]
+    TooLarge(u64),
+    #[error("Download is not finished in {0:?}")]
+    Timeout(Duration),
+    #[error("Downloaded tar.gz archive has no .mmdb file")]
+    NoDatabaseInArchive,
+    #[error("Database is not valid: {0}")]
+    Invalid(#[from] MaxMindDBError),
+    #[error(transparent)]
+    Join(#[from] tokio::task::JoinError),
+}
+
+impl From<StatusCode> for MaxMindDbUpdateError {
+    fn from(status_code: StatusCode) -> Self {
+        MaxMindDbUpdateError::NonSuccess(status_code)
+    }
+}
+
+impl From<DownloadError> for MaxMindDbUpdateError {
+    fn from(error: DownloadError) -> Self {
+        match error {
+            DownloadError::Hyper(error) => Self::Hyper(error),
+            DownloadError::Http(error) => Self::Http(error),
+            DownloadError::NonSuccess(status_code) => Self::NonSuccess(status_code),
+            DownloadError::TooLarge(max_size) => Self::TooLarge(max_size),
+        }
+    }
+}
+
+/// Where to download the database from, either .mmdb file or tar.gz archive with it
+#[derive(Debug, Clone)]
+struct MaxMindDbDownload {
+    uri: Uri,
+    /// Basic authorization of MaxMind account, sent to the original host only
+    authorization: Option<HeaderValue>,
+    max_size: u64,
+    timeout: Duration,
+}
+
+impl MaxMindDbDownload {
+    /// URI without query, which may have the license key
+    fn redacted_uri(&self) -> String {
+        match self.uri.authority() {
+            Some(authority) => format!("{authority}{}", self.uri.path()),
+            None => self.uri.path().to_owned(),
+        }
+    }
+
+    /// Download and validate the database, `None` is returned if it is not modified since the
+    /// download `validators` are taken from, otherwise they are updated
+    async fn fetch<C>(
+        &self,
+        client: &Client<C>,
+        validators: &mut HttpValidators,
+    ) -> Result<Option<Vec<u8>>, MaxMindDbUpdateError>
+    where
+        C: Connect + Clone + Send + Sync + 'static,
+    {
+        let future = fetch(
+            client,
+            &self.uri,
+            self.authorization.as_ref(),
+            validators,
+            self.max_size,
+        );
+        let (body, new_validators) = match tokio::time::timeout(self.timeout, future)
+            .await
+            .map_err(|_| MaxMindDbUpdateError::Timeout(self.timeout))??
+        {
+            Some(value) => value,
+            None => return Ok(None),
+        };
+        let database = if body.starts_with(&[0x1f, 0x8b]) {
+            Self::database_from_tar_gz(&body, self.max_size)?
+        } else {
+            body.to_vec()
+        };
+        maxminddb::Reader::from_source(database.as_slice())?;
+        *validators = new_validators;
+        Ok(Some(database))
+    }
+
+    /// MaxMind archives have a single directory with .mmdb file and license files
+    fn database_from_tar_gz(
+        archive: &[u8],
+        max_size: u64,
+    ) -> Result<Vec<u8>, MaxMindDbUpdateError> {
+        let mut tar_archive = tar::Archive::new(flate2::bufread::GzDecoder::new(archive));
+        for entry in tar_archive.entries()? {
+            let entry = entry?;
+            if entry.path()?.extension() != Some("mmdb".as_ref()) {
+                continue;
+            }
+            if entry.size() > max_size {
+                return Err(MaxMindDbUpdateError::TooLarge(max_size));
+            }
+            let mut database = Vec::with_capacity(entry.size() as usize);
+            entry.take(max_size).read_to_end(&mut database)?;
+            return Ok(database);
+        }
+        Err(MaxMindDbUpdateError::NoDatabaseInArchive)
+    }
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(try_from = "MaxMindDbUpdaterConfig")]
+pub struct MaxMindDbUpdater {
+    interval: Duration,
+    download: Option<MaxMindDbDownload>,
+    /// Database file to watch and to write downloaded database to
+    path: PathBuf,
+    /// Stamp of the file the current database is loaded from
+    stamp: Option<FileStamp>,
+    /// Validators of the last download, so the first update after restart is conditional too
+    validators: HttpValidators,
+    /// Memory-map the file instead of reading it into memory
+    mmap: bool,
+    handle: Option<tokio::task::JoinHandle<()>>,
+}
+
+impl MaxMindDbUpdater {
+    pub fn default_interval() -> NonZeroDuration {
+        NonZeroDuration::from_secs(MAXMIND_DB_UPDATE_INTERVAL_SECONDS).unwrap()
+    }
+
+    pub fn default_max_size() -> u64 {
+        MAXMIND_DB_MAX_SIZE
+    }
+
+    pub fn default_timeout() -> NonZeroDuration {
+        NonZeroDuration::from_secs(MAXMIND_DB_DOWNLOAD_TIMEOUT_SECONDS).unwrap()
+    }
+
+    pub fn default_edition_id() -> String {
+        MAXMIND_DB_DEFAULT_EDITION_ID.to_owned()
+    }
+}
+
+impl Default for MaxMindDbUpdater {
+    fn default() -> Self {
+        Self::new(Self::default_interval())
+    }
+}
+
+#[derive(Deserialize, Debug)]
+struct MaxMindDbUpdaterConfig {
+    #[serde(default = "MaxMindDbUpdater::default_interval")]
+    interval: NonZeroDuration,
+    /// URL of .mmdb file or tar.gz archive with it
+    #[serde(default, alias = "uri")]
+    url: Option<String>,
+    /// MaxMind license key, the database is downloaded from MaxMind if specified
+    #[serde(default)]
+    license_key: Option<String>,
+    /// MaxMind account ID, the legacy download URL with license key in query is used if not
+    /// specified
+    #[serde(default)]
+    account_id: Option<u64>,
+    #[serde(default = "MaxMindDbUpdater::default_edition_id")]
+    edition_id: String,
+    /// Maximum download size in bytes
+    #[serde(default = "MaxMindDbUpdater::default_max_size")]
+    max_size: u64,
+    /// Total download timeout in seconds
+    #[serde(default = "MaxMindDbUpdater::default_timeout")]
+    timeout: NonZeroDuration,
+}
+
+impl MaxMindDbUpdaterConfig {
+    fn is_url_safe(s: &str) -> bool {
+        !s.is_empty()
+            && s.bytes()
+                .all(|b| b.is_ascii_alphanumeric() || b == b'-' || b == b'_')
+    }
+
+    /// Download URI and authorization header
+    fn download_source(&self) -> Result<Option<(Uri, Option<HeaderValue>)>, String> {
+        let Self {
+            url,
+            license_key,
+            account_id,
+            edition_id,
+            ..
+        } = self;
+        let license_key = match (url, license_key) {
+            (Some(_), Some(_)) => return Err("url and license_key are mutually exclusive".into()),
+            (Some(url), None) => {
+                let uri: Uri = url
+                    .parse()
+                    .map_err(|e| format!(r#"invalid URL "{url}": {e}"#))?;
+                if uri.scheme().is_none() || uri.host().is_none() {
+                    return Err(format!(r#"URL "{url}" must be absolute"#));
+                }
+                return Ok(Some((uri, None)));
+            }
+            (None, Some(license_key)) => license_key,
+            (None, None) if account_id.is_some() => {
+                return Err("account_id requires license_key".into())
+            }
+            (None, None) => return Ok(None),
+        };
+        if !Self::is_url_safe(license_key) || !Self::is_url_safe(edition_id) {
+            return Err("license_key and edition_id must be alphanumeric".into());
+        }
+        let source = match account_id {
+            Some(account_id) => {
+                let credentials = base64::engine::general_purpose::STANDARD
+                    .encode(format!("{account_id}:{license_key}"));
+                let authorization = HeaderValue::from_str(&format!("Basic {credentials}"))
+                    .expect("base64 is a valid header value");
+                let uri = format!(
+                    "https://download.maxmind.com/geoip/databases/{edition_id}/download?suffix=tar.gz"
+                );
+                (uri, Some(authorization))
+            }
+            None => {
+                let uri = format!("https://download.maxmind.com/app/geoip_download?edition_id={edition_id}&license_key={license_key}&suffix=tar.gz");
+                (uri, None)
+            }
+        };
+        Ok(Some((
+            source.0.parse().expect("MaxMind URL must be valid"),
+            source.1,
+        )))
+    }
+}
+
+impl TryFrom<MaxMindDbUpdaterConfig> for MaxMindDbUpdater {
+    type Error = String;
+
+    fn try_from(config: MaxMindDbUpdaterConfig) -> Result<Self, Self::Error> {
+        let download = config
+            .download_source()?
+            .map(|(uri, authorization)| MaxMindDbDownload {
+                uri,
+                authorization,
+                max_size: config.max_size,
+                timeout: config.timeout.clone().into(),
+            });
+        Ok(Self {
+            download,
+            ..Self::new(config.interval)
+        })
+    }
+}
+
+impl MaxMindDbUpdater {
+    pub fn new(interval: impl Into<Duration>) -> Self {
+        Self {
+            interval: interval.into(),
+            download: None,
+            path: PathBuf::new(),
+            stamp: None,
+            validators: HttpValidators::default(),
+            mmap: false,
+            handle: None,
+        }
+    }
+
+    /// Database file to watch and to write downloaded database to
+    pub fn with_path(mut self, path: PathBuf) -> Self {
+        self.path = path;
+        self
+    }
+
+    /// Stamp of the file the initial database is loaded from
+    pub fn with_stamp(mut self, stamp: Option<FileStamp>) -> Self {
+        self.stamp = stamp;
+        self
+    }
+
+    /// Validators of the download the initial database is from
+    pub fn with_validators(mut self, validators: HttpValidators) -> Self {
+        self.validators = validators;
+        self
+    }
+
+    pub fn with_mmap(mut self, mmap: bool) -> Self {
+        self.mmap = mmap;
+        self
+    }
+
+    pub fn downloads(&self) -> bool {
+        self.download.is_some()
+    }
+
+    /// Download database to the file, it is used at startup if there is no file yet. Validators
+    /// of the download are returned to be passed to [MaxMindDbUpdater::with_validators]
+    pub fn download_blocking(
+        &self,
+        handle: Option<Handle>,
+    ) -> Result<HttpValidators, MaxMindDbUpdateError> {
+        let mut validators = HttpValidators::default();
+        let download = match &self.download {
+            Some(download) => download,
+            None => return Ok(validators),
+        };
+        let https = HttpsConnector::new();
+        let client = Client::builder().build::<_, Body>(https);
+        let handle = handle.unwrap_or_else(Handle::current);
+        handle.block_on(Self::download_to_file(
+            &client,
+            download,
+            &self.path,
+            &mut validators,
+        ))?;
+        Ok(validators)
+    }
+
+    /// Download database and replace the file with it, `false` is returned if the database is not
+    /// modified
+    async fn download_to_file<C>(
+        client: &Client<C>,
+        download: &MaxMindDbDownload,
+        path: &Path,
+        validators: &mut HttpValidators,
+    ) -> Result<bool, MaxMindDbUpdateError>
+    where
+        C: Connect + Clone + Send + Sync + 'static,
+    {
+        let database = match download.fetch(client, validators).await? {
+            Some(database) => database,
+            None => return Ok(false),
+        };
+        let path = path.to_owned();
+        tokio::task::spawn_blocking(move || write_atomically(&path, &database)).await??;
+        Ok(true)
+    }
+
+    /// Read the database if the file stamp differs from `current`
+    async fn reload(
+        path: &Path,
+        current: Option<FileStamp>,
+        mmap: bool,
+    ) -> Result<Option<(MaxMindDbReader, FileStamp)>, MaxMindDbUpdateError> {
+        let path = path.to_owned();
+        tokio::task::spawn_blocking(move || {
+            let stamp = FileStamp::new(&path)?;
+            if Some(stamp) == current {
+                return Ok(None);
+            }
+            let reader = open_reader(&path, mmap)?;
+            Ok(Some((reader, stamp)))
+        })
+        .await?
+    }
+
+    pub fn start(&mut self, geo: &MaxMindDbGeo) -> Option<&tokio::task::JoinHandle<()>> {
+        if self.handle.is_some() {
+            return None;
+        }
+
+        let https = HttpsConnector::new();
+        let client = Client::builder().build::<_, Body>(https);
+        let reader_lock = geo.maxminddb_reader.clone();
+        let interval = self.interval;
+        let download = self.download.clone();
+        let path = self.path.clone();
+        let mut stamp = self.stamp;
+        let mmap = self.mmap;
+        let mut validators = self.validators.clone();
+
+        self.handle = tokio::spawn(async move {
+            loop {
+                tokio::time::sleep(interval).await;
+                if let Some(download) = &download {
+                    let uri = download.redacted_uri();
+                    match Self::download_to_file(&client, download, &path, &mut validators).await {
+                        Ok(true) => log::info!(r#"MaxMind DB is downloaded from "{uri}""#),
+                        Ok(false) => log::debug!(r#"MaxMind DB at "{uri}" is not modified"#),
+                        Err(err) => log::warn!(
+                            r#"Error while attempting to download MaxMind DB from "{uri}": {err}"#
+                        ),
+                    }
+                }
+                match Self::reload(&path, stamp, mmap).await {
+                    Ok(Some((reader, new_stamp))) => {
+                        *reader_lock.write().unwrap() = reader;
+                        stamp = Some(new_stamp);
+                        log::info!("MaxMind DB is reloaded from {path:?}");
+                    }
+                    Ok(None) => {}
+                    Err(err) => log::warn!(
+                        "Cannot reload MaxMind DB from {path:?}, keeping the current one: {err}"
+                    ),
+                }
+            }
+        })
+        .into();
+        self.handle.as_ref()
+    }
+}
+
+impl MaxMindDbGeo {
+    pub fn set_updater(&mut self, updater: Option<MaxMindDbUpdater>) {
+        self.updater = updater.map(|updater| Box::new(RwLock::new(updater)));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Minimal IPv4 database with all addresses in the continent with the given GeoNames ID
+    fn database(geoname_id: u32) -> Vec<u8> {
+        fn string(s: &str) -> Vec<u8> {
+            let mut v = vec![0x40 | s.len() as u8];
+            v.extend_from_slice(s.as_bytes());
+            v
+        }
+        fn uint16(value: u16) -> Vec<u8> {
+            let mut v = vec![0xa2];
+            v.extend_from_slice(&value.to_be_bytes());
+            v
+        }
+
+        // Single node, both records point to the start of data section
+        let data_pointer: u32 = 1 + 16;
+        let mut db = vec![];
+        for _ in 0..2 {
+            db.extend_from_slice(&data_pointer.to_be_bytes()[1..]);
+        }
+        db.extend_from_slice(&[0; 16]);
+        // {"continent": {"geoname_id": uint32}}
+        db.push(0xe1);
+        db.extend(string("continent"));
+        db.push(0xe1);
+        db.extend(string("geoname_id"));
+        db.push(0xc4);
+        db.extend_from_slice(&geoname_id.to_be_bytes());
+
+        db.extend_from_slice(b"\xab\xcd\xefMaxMind.com");
+        db.push(0xe9);
+        db.extend(string("binary_format_major_version"));
+        db.extend(uint16(2));
+        db.extend(string("binary_format_minor_version"));
+        db.extend(uint16(0));
+        db.extend(string("build_epoch"));
+        // uint64 is an extended type
+        db.extend_from_slice(&[0x01, 0x02, 0x01]);
+        db.extend(string("database_type"));
+        db.extend(string("Test"));
+        db.extend(string("description"));
+        db.push(0xe0);
+        db.extend(string("ip_version"));
+        db.extend(uint16(4));
+        db.extend(string("languages"));
+        // array is an extended type
+        db.extend_from_slice(&[0x00, 0x04]);
+        db.extend(string("node_count"));
+        db.extend_from_slice(&[0xc1, 0x01]);
+        db.extend(string("record_size"));
+        db.extend(uint16(24));
+        db
+    }
+
+    const EUROPE: u32 = 6255148;
+    const ASIA: u32 = 6255147;
+
+    fn temp_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!(
+            "geo302-maxminddb-{name}-{}.mmdb",
+            std::process::id()
+        ))
+    }
+
+    #[tokio::test]
+    async fn reload() {
+        let path = temp_path("reload");
+        std::fs::write(&path, database(EUROPE)).unwrap();
+        let stamp = FileStamp::new(&path).unwrap();
+        let geo = MaxMindDbGeo::from_file(&path).unwrap();
+        let address: IpAddr = "2.3.4.5".parse().unwrap();
+        assert_eq!(
+            geo.try_lookup_continent(address).unwrap(),
+            Continent::Europe
+        );
+
+        assert!(MaxMindDbUpdater::reload(&path, Some(stamp), false)
+            .await
+            .unwrap()
+            .is_none());
+
+        // The stamp compares sizes too, mtime may be unchanged
+        let mut database = database(ASIA);
+        database.push(0);
+        write_atomically(&path, &database).unwrap();
+        let (reader, new_stamp) = MaxMindDbUpdater::reload(&path, Some(stamp), false)
+            .await
+            .unwrap()
+            .unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_ne!(new_stamp, stamp);
+        *geo.maxminddb_reader.write().unwrap() = reader;
+        assert_eq!(geo.try_lookup_continent(address).unwrap(), Continent::Asia);
+    }
+
+    #[tokio::test]
+    async fn reload_mmap() {
+        let path = temp_path("mmap");
+        std::fs::write(&path, database(EUROPE)).unwrap();
+        let stamp = FileStamp::new(&path).unwrap();
+        let geo = MaxMindDbGeo::open(&path, true).unwrap();
+        let address: IpAddr = "2.3.4.5".parse().unwrap();
+
+        let mut database = database(ASIA);
+        database.push(0);
+        write_atomically(&path, &database).unwrap();
+        // Renamed file doesn't affect the mapping of the old one
+        assert_eq!(
+            geo.try_lookup_continent(address).unwrap(),
+            Continent::Europe
+        );
+        let (reader, _stamp) = MaxMindDbUpdater::reload(&path, Some(stamp), true)
+            .await
+            .unwrap()
+            .unwrap();
+        std::fs::remove_file(&path).unwrap();
+        *geo.maxminddb_reader.write().unwrap() = reader;
+        assert_eq!(geo.try_lookup_continent(address).unwrap(), Continent::Asia);
+    }
+
+    fn tar_gz(database: &[u8]) -> Vec<u8> {
+        let gz = flate2::write::GzEncoder::new(vec![], flate2::Compression::fast());
+        let mut builder = tar::Builder::new(gz);
+        for (path, content) in [
+            (
+                "GeoLite2-Country_20231010/LICENSE.txt",
+                b"license".as_slice(),
+            ),
+            ("GeoLite2-Country_20231010/GeoLite2-Country.mmdb", database),
+        ] {
+            let mut header = tar::Header::new_gnu();
+            header.set_size(content.len() as u64);
+            header.set_mode(0o644);
+            header.set_cksum();
+            builder.append_data(&mut header, path, content).unwrap();
+        }
+        builder.into_inner().unwrap().finish().unwrap()
+    }
+
+    async fn serve(body: Vec<u8>) -> Uri {
+        crate::download::tests::serve(body, "GeoLite2-Country.tar.gz").await
+    }
+
+    #[tokio::test]
+    async fn download() {
+        let uri = serve(tar_gz(&database(EUROPE))).await;
+        let download = MaxMindDbDownload {
+            uri,
+            authorization: None,
+            max_size: MaxMindDbUpdater::default_max_size(),
+            timeout: Duration::from_secs(10),
+        };
+        let path = temp_path("download");
+        let client = Client::new();
+        let mut validators = HttpValidators::default();
+        assert!(
+            MaxMindDbUpdater::download_to_file(&client, &download, &path, &mut validators)
+                .await
+                .unwrap()
+        );
+        assert_eq!(validators.etag.as_ref().unwrap(), "\"v1\"");
+        assert!(
+            !MaxMindDbUpdater::download_to_file(&client, &download, &path, &mut validators)
+                .await
+                .unwrap()
+        );
+        let geo = MaxMindDbGeo::from_file(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(
+            geo.try_lookup_continent("2.3.4.5".parse().unwrap())
+                .unwrap(),
+            Continent::Europe
+        );
+    }
+
+    #[tokio::test]
+    async fn invalid_download() {
+        let uri = serve(b"not a database".to_vec()).await;
+        let download = MaxMindDbDownload {
+            uri,
+            authorization: None,
+            max_size: MaxMindDbUpdater::default_max_size(),
+            timeout: Duration::from_secs(10),
+        };
+        let mut validators = HttpValidators::default();
+        let result = download.fetch(&Client::new(), &mut validators).await;
+        assert!(matches!(result, Err(MaxMindDbUpdateError::Invalid(_))));
+        assert_eq!(validators, HttpValidators::default());
+    }
+
+    #[test]
+    fn config() {
+        let updater: MaxMindDbUpdater = toml::from_str(r#"license_key = "abc_123""#).unwrap();
+        let download = updater.download.unwrap();
+        assert_eq!(download.uri, "https://download.maxmind.com/app/geoip_download?edition_id=GeoLite2-Country&license_key=abc_123&suffix=tar.gz");
+        assert_eq!(
+            download.redacted_uri(),
+            "download.maxmind.com/app/geoip_download"
+        );
+        assert!(download.authorization.is_none());
+
+        let updater: MaxMindDbUpdater = toml::from_str(
+            r#"
+                license_key = "abc_123"
+                account_id = 42
+                edition_id = "GeoLite2-City"
+            "#,
+        )
+        .unwrap();
+        let download = updater.download.unwrap();
+        assert_eq!(
+            download.uri,
+            "https://download.maxmind.com/geoip/databases/GeoLite2-City/download?suffix=tar.gz"
+        );
+        // base64 of "42:abc_123"
+        assert_eq!(download.authorization.unwrap(), "Basic NDI6YWJjXzEyMw==");
+
+        let updater: MaxMindDbUpdater = toml::from_str("interval = 60").unwrap();
+        assert!(updater.download.is_none());
+        assert_eq!(updater.interval, Duration::from_secs(60));
+
+        for s in [
+            r#"url = "https://example.org/db.mmdb"
+               license_key = "abc""#,
+            r#"account_id = 42"#,
+            r#"license_key = "abc&edition_id=x""#,
+            r#"url = "/db.mmdb""#,
+        ] {
+            assert!(toml::from_str::<MaxMindDbUpdater>(s).is_err(), "{s}");
+        }
+    }
+}
@@ -1,5 +1,7 @@
 pub use continent::Continent;
 pub use error::GeoError;
+#[cfg(feature = "maxminddb")]
+use max_mind_db::{config::MaxMindDbConfig, MaxMindDbGeo};
 #[cfg(feature = "ripe-geo")]
 use ripe_geo::{config::RipeGeoConfig, RipeGeo, RipeGeoImpl};
 
@@ -13,8 +15,6 @@ pub mod ripe_geo;
 use enum_dispatch::enum_dispatch;
 use serde::Deserialize;
 use std::net::IpAddr;
-#[cfg(feature = "maxminddb")]
-use std::path::PathBuf;
 
 #[enum_dispatch]
 pub enum Geo {
@@ -48,7 +48,7 @@ pub enum GeoConfig {
         alias = "MaxMind",
         alias = "Max Mind"
     )]
-    MaxMindDb { path: PathBuf },
+    MaxMindDb(MaxMindDbConfig),
     #[cfg(feature = "ripe-geo")]
     #[serde(alias = "ripe-geo", alias = "ripegeo", alias = "ripe geo")]
     RipeGeo(RipeGeoConfig),
@@ -58,10 +58,9 @@ impl GeoConfig {
     pub fn load(self) -> Result<Geo, GeoError> {
         match self {
             #[cfg(feature = "maxminddb")]
-            Self::MaxMindDb { path } => {
-                let geo = Geo::MaxMindDb(max_mind_db::MaxMindDbGeo::from_file(&path)?);
-                log::info!("Maxmind DB is loaded from {path:?}");
-                Ok(geo)
+            Self::MaxMindDb(config) => {
+                let max_mind_db: MaxMindDbGeo = config.try_into()?;
+                Ok(Geo::MaxMindDb(max_mind_db))
             }
             #[cfg(feature = "ripe-geo")]
             Self::RipeGeo(config) => {
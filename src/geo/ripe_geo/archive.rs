@@ -0,0 +1,338 @@
+use super::*;
+
+use std::io::Cursor;
+
+/// Supported formats of downloaded or local archives
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ArchiveFormat {
+    Tar,
+    TarGz,
+    TarZst,
+    Zip,
+}
+
+impl ArchiveFormat {
+    /// Detect format by the magic bytes, so neither file name nor Content-Type are trusted
+    pub fn detect(archive: &[u8]) -> Option<Self> {
+        const TAR_MAGIC_OFFSET: usize = 257;
+
+        if archive.starts_with(&[0x1f, 0x8b]) {
+            Some(Self::TarGz)
+        } else if archive.starts_with(&[0x28, 0xb5, 0x2f, 0xfd]) {
+            Some(Self::TarZst)
+        } else if archive.starts_with(b"PK\x03\x04") || archive.starts_with(b"PK\x05\x06") {
+            Some(Self::Zip)
+        } else if archive
+            .get(TAR_MAGIC_OFFSET..)
+            .map_or(false, |rest| rest.starts_with(b"ustar"))
+        {
+            Some(Self::Tar)
+        } else {
+            None
+        }
+    }
+}
+
+fn unpacked_too_large(max_unpacked_size: u64) -> std::io::Error {
+    std::io::Error::new(
+        std::io::ErrorKind::InvalidData,
+        format!("Unpacked archive is larger than {max_unpacked_size} bytes"),
+    )
+}
+
+/// Reader which fails instead of reading more than the limit, so a small compressed archive
+/// cannot exhaust memory
+struct LimitedReader<R> {
+    inner: R,
+    remaining: u64,
+    limit: u64,
+}
+
+impl<R> LimitedReader<R> {
+    fn new(inner: R, limit: u64) -> Self {
+        Self {
+            inner,
+            remaining: limit,
+            limit,
+        }
+    }
+}
+
+impl<R: Read> Read for LimitedReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        if self.remaining == 0 {
+            // The stream may end exactly at the limit
+            return match self.inner.read(&mut [0])? {
+                0 => Ok(0),
+                _ => Err(unpacked_too_large(self.limit)),
+            };
+        }
+        let max = buf
+            .len()
+            .min(usize::try_from(self.remaining).unwrap_or(usize::MAX));
+        let read = self.inner.read(&mut buf[..max])?;
+        self.remaining -= read as u64;
+        Ok(read)
+    }
+}
+
+impl RipeGeoImpl {
+    /// Parse tar, tar.gz, tar.zst or zip archive or load a snapshot, the format is detected from
+    /// the content. Files sizes declared by the archive are not trusted, reading fails when
+    /// unpacked files exceed `max_unpacked_size` bytes in total
+    pub fn from_archive(
+        archive: &[u8],
+        overlaps_strategy: RipeGeoOverlapsStrategy,
+        max_unpacked_size: u64,
+    ) -> Result<Self, RipeGeoDataError> {
+        if Self::is_snapshot(archive) {
+            return Ok(Self::from_snapshot(archive)?);
+        }
+        match ArchiveFormat::detect(archive).ok_or(RipeGeoDataError::UnknownArchiveFormat)? {
+            ArchiveFormat::Tar => Self::from_tar(archive, overlaps_strategy, max_unpacked_size),
+            ArchiveFormat::TarGz => Self::from_tar(
+                flate2::bufread::GzDecoder::new(archive),
+                overlaps_strategy,
+                max_unpacked_size,
+            ),
+            ArchiveFormat::TarZst => {
+                let decoder = ruzstd::StreamingDecoder::new(archive).map_err(|error| {
+                    RipeGeoDataError::ArchiveReadError(std::io::Error::new(
+                        std::io::ErrorKind::InvalidData,
+                        error.to_string(),
+                    ))
+                })?;
+                Self::from_tar(decoder, overlaps_strategy, max_unpacked_size)
+            }
+            ArchiveFormat::Zip => Self::from_zip(archive, overlaps_strategy, max_unpacked_size),
+        }
+    }
+
+    fn from_tar(
+        reader: impl Read,
+        overlaps_strategy: RipeGeoOverlapsStrategy,
+        max_unpacked_size: u64,
+    ) -> Result<Self, RipeGeoDataError> {
+        let mut tar_archive = tar::Archive::new(LimitedReader::new(reader, max_unpacked_size));
+        let it = tar_archive
+            .entries()
+            .map_err(RipeGeoDataError::ArchiveReadError)?
+            .filter_map(|entry| {
+                let mut entry = entry.ok()?;
+                let path = entry.path().ok()?;
+                let path = path.into_owned();
+                let mut vec = vec![];
+                if let Err(error) = entry.read_to_end(&mut vec) {
+                    return Some(Err(RipeGeoDataError::ArchiveEntryIoError { path, error }));
+                }
+                let boxed_entry: Box<dyn Read> = Box::new(Cursor::new(vec));
+                Some(Ok((path, boxed_entry)))
+            });
+        Self::from_text_files(it, overlaps_strategy)
+    }
+
+    fn from_zip(
+        archive: &[u8],
+        overlaps_strategy: RipeGeoOverlapsStrategy,
+        max_unpacked_size: u64,
+    ) -> Result<Self, RipeGeoDataError> {
+        let mut zip_archive = zip::ZipArchive::new(Cursor::new(archive))
+            .map_err(|error| RipeGeoDataError::ArchiveReadError(error.into()))?;
+        let mut remaining = max_unpacked_size;
+        let it = (0..zip_archive.len()).filter_map(move |index| {
+            let file = zip_archive.by_index(index).ok()?;
+            if file.is_dir() {
+                return None;
+            }
+            // Entries with absolute paths or ".." components are skipped
+            let path = file.enclosed_name()?.to_owned();
+            let mut vec = vec![];
+            // One byte more to tell the file of the remaining size from a larger one
+            if let Err(error) = file.take(remaining.saturating_add(1)).read_to_end(&mut vec) {
+                return Some(Err(RipeGeoDataError::ArchiveEntryIoError { path, error }));
+            }
+            remaining = match remaining.checked_sub(vec.len() as u64) {
+                Some(remaining) => remaining,
+                None => {
+                    let error = unpacked_too_large(max_unpacked_size);
+                    return Some(Err(RipeGeoDataError::ArchiveEntryIoError { path, error }));
+                }
+            };
+            let boxed_entry: Box<dyn Read> = Box::new(Cursor::new(vec));
+            Some(Ok((path, boxed_entry)))
+        });
+        Self::from_text_files(it, overlaps_strategy)
+    }
+}
+
+#[cfg(test)]
+pub(super) mod tests {
+    use super::*;
+
+    use std::io::Write;
+
+    const FILES: [(&str, &str, &str); 6] = [
+        ("africa", "41.0.0.0/8", "2c0f::/16"),
+        ("asia", "1.0.0.0/8", "2400::/12"),
+        ("europe", "2.0.0.0/8", "2a00::/12"),
+        ("north-america", "3.0.0.0/8", "2600::/12"),
+        ("oceania", "4.0.0.0/8", "2001::/16"),
+        ("south-america", "5.0.0.0/8", "2800::/12"),
+    ];
+
+    /// (path, content) of all files of a minimal valid database
+    fn files() -> impl Iterator<Item = (String, String)> {
+        FILES.into_iter().flat_map(|(continent, ipv4, ipv6)| {
+            [("ipv4", ipv4), ("ipv6", ipv6)].map(|(ip, record)| {
+                (
+                    format!("continents/{continent}.{ip}.list"),
+                    format!("{record}\n"),
+                )
+            })
+        })
+    }
+
+    pub fn tar() -> Vec<u8> {
+        let mut builder = tar::Builder::new(vec![]);
+        for (path, content) in files() {
+            let mut header = tar::Header::new_gnu();
+            header.set_size(content.len() as u64);
+            header.set_mode(0o644);
+            header.set_cksum();
+            builder
+                .append_data(&mut header, path, content.as_bytes())
+                .unwrap();
+        }
+        builder.into_inner().unwrap()
+    }
+
+    pub fn tar_gz() -> Vec<u8> {
+        let mut gz = flate2::write::GzEncoder::new(vec![], flate2::Compression::fast());
+        gz.write_all(&tar()).unwrap();
+        gz.finish().unwrap()
+    }
+
+    /// zstd frame of a single raw (not compressed) block
+    fn tar_zst() -> Vec<u8> {
+        let tar = tar();
+        let size = tar.len();
+        assert!((256..65536 + 256).contains(&size) && size < 128 << 10);
+        let mut frame = vec![0x28, 0xb5, 0x2f, 0xfd];
+        // Single segment with two-byte content size, which is stored minus 256
+        frame.push(0x60);
+        frame.extend_from_slice(&((size - 256) as u16).to_le_bytes());
+        // The last block of raw type
+        let block_header = ((size as u32) << 3) | 1;
+        frame.extend_from_slice(&block_header.to_le_bytes()[..3]);
+        frame.extend_from_slice(&tar);
+        frame
+    }
+
+    fn zip() -> Vec<u8> {
+        let mut writer = zip::ZipWriter::new(Cursor::new(vec![]));
+        writer
+            .add_directory("continents", Default::default())
+            .unwrap();
+        for (path, content) in files() {
+            writer.start_file(path, Default::default()).unwrap();
+            writer.write_all(content.as_bytes()).unwrap();
+        }
+        writer.finish().unwrap().into_inner()
+    }
+
+    #[test]
+    fn formats() {
+        for (archive, format) in [
+            (tar(), ArchiveFormat::Tar),
+            (tar_gz(), ArchiveFormat::TarGz),
+            (tar_zst(), ArchiveFormat::TarZst),
+            (zip(), ArchiveFormat::Zip),
+        ] {
+            assert_eq!(ArchiveFormat::detect(&archive), Some(format));
+            let ripe_geo_impl =
+                RipeGeoImpl::from_archive(&archive, RipeGeoOverlapsStrategy::Skip, u64::MAX)
+                    .unwrap();
+            assert_eq!(
+                ripe_geo_impl
+                    .try_lookup_continent("2.3.4.5".parse().unwrap())
+                    .unwrap(),
+                Continent::Europe,
+                "{format:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn snapshot() {
+        let snapshot = RipeGeoImpl::from_archive(&tar(), RipeGeoOverlapsStrategy::Skip, u64::MAX)
+            .unwrap()
+            .to_snapshot();
+        let ripe_geo_impl =
+            RipeGeoImpl::from_archive(&snapshot, RipeGeoOverlapsStrategy::Skip, u64::MAX).unwrap();
+        assert_eq!(
+            ripe_geo_impl
+                .try_lookup_continent("2.3.4.5".parse().unwrap())
+                .unwrap(),
+            Continent::Europe
+        );
+    }
+
+    #[test]
+    fn max_unpacked_size() {
+        let unpacked_size = files()
+            .map(|(_path, content)| content.len() as u64)
+            .sum::<u64>();
+        let tar_size = tar().len() as u64;
+        // tar reader stops at the first empty block, so only a half of the tar is certainly read
+        for (archive, format, max_size, too_small_size) in [
+            (tar(), ArchiveFormat::Tar, tar_size, tar_size / 2),
+            (tar_gz(), ArchiveFormat::TarGz, tar_size, tar_size / 2),
+            (tar_zst(), ArchiveFormat::TarZst, tar_size, tar_size / 2),
+            (zip(), ArchiveFormat::Zip, unpacked_size, unpacked_size - 1),
+        ] {
+            assert!(
+                RipeGeoImpl::from_archive(&archive, RipeGeoOverlapsStrategy::Skip, max_size)
+                    .is_ok(),
+                "{format:?}"
+            );
+            assert!(
+                matches!(
+                    RipeGeoImpl::from_archive(
+                        &archive,
+                        RipeGeoOverlapsStrategy::Skip,
+                        too_small_size
+                    ),
+                    Err(RipeGeoDataError::ArchiveEntryIoError { .. })
+                ),
+                "{format:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn huge_declared_size() {
+        let mut header = tar::Header::new_gnu();
+        header.set_path("continents/europe.ipv4.list").unwrap();
+        header.set_size(1 << 50);
+        header.set_mode(0o644);
+        header.set_cksum();
+        let mut archive = header.as_bytes().to_vec();
+        archive.extend_from_slice(&[b'\n'; 512]);
+        // Declared size must not be pre-allocated, the truncated content is just invalid
+        assert!(
+            RipeGeoImpl::from_archive(&archive, RipeGeoOverlapsStrategy::Skip, u64::MAX).is_err()
+        );
+    }
+
+    #[test]
+    fn unknown_format() {
+        assert_eq!(ArchiveFormat::detect(b"asia.ipv4.list"), None);
+        assert!(matches!(
+            RipeGeoImpl::from_archive(b"", RipeGeoOverlapsStrategy::Skip, u64::MAX),
+            Err(RipeGeoDataError::UnknownArchiveFormat)
+        ));
+    }
+}
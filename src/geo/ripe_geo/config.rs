@@ -1,12 +1,11 @@
 #[cfg(feature = "ripe-geo-autoupdate")]
-use super::updater::RipeGeoUpdater;
+use super::location::ArchiveLocation;
+#[cfg(feature = "ripe-geo-autoupdate")]
+use super::updater::{ArchiveValidators, RipeGeoDownloadOptions, RipeGeoUpdater};
 use super::*;
 #[cfg(not(feature = "ripe-geo-autoupdate"))]
 use crate::unavailable::Unavailable;
 
-#[cfg(feature = "ripe-geo-autoupdate")]
-use hyper::http::Uri;
-
 #[derive(Deserialize, Debug)]
 pub struct RipeGeoConfig {
     #[serde(default)]
@@ -15,50 +14,90 @@ pub struct RipeGeoConfig {
     overlaps: RipeGeoOverlapsStrategy,
     #[serde(default)]
     autoupdate: RipeGeoAutoupdateConfig,
+    /// Directory to store downloaded archive in and to load it from at startup
+    #[serde(default)]
+    cache_dir: Option<RipeGeoCacheDir>,
+}
+
+/// Where the database was loaded from at startup
+#[derive(Debug)]
+enum RipeGeoSource {
+    Path,
+    #[cfg(feature = "ripe-geo-autoupdate")]
+    Cache,
+    #[cfg(feature = "ripe-geo-autoupdate")]
+    Uri(ArchiveValidators),
+    #[cfg(feature = "ripe-geo-embedded")]
+    Embedded,
 }
 
 impl RipeGeoConfig {
     /// Parse config with respect to Cargo features
     /// - Load from path is specified
-    /// - If autoupdate is enabled, download from web
+    /// - If autoupdate is enabled, load from cache_dir if it has a valid archive, or download
+    ///   from web or read from local file otherwise
     /// - If not (or download failed), but embedded is enabled, load from binary
     /// - Return error otherwise
-    fn ripe_geo_impl(&self) -> Result<RipeGeoImpl, GeoError> {
-        // autoupdate could be unused
+    fn ripe_geo_impl(&self) -> Result<(RipeGeoImpl, RipeGeoSource), GeoError> {
+        // autoupdate and cache_dir could be unused
         #[allow(unused_variables)]
         let Self {
             path,
             overlaps,
             autoupdate,
+            cache_dir,
         } = self;
         // We would like to move to the None branch when this stabilizes
         // https://github.com/rust-lang/rust/issues/15701
         #[allow(unreachable_code)]
         match path {
             Some(path) => {
-                let ripe_geo_impl = RipeGeoImpl::from_folder(path, *overlaps)?;
+                let ripe_geo_impl = RipeGeoImpl::from_path(path, *overlaps)?;
                 log::info!("ripe-geo database is loaded from {path:?}");
-                Ok(ripe_geo_impl)
+                Ok((ripe_geo_impl, RipeGeoSource::Path))
             }
             None => {
                 #[cfg(feature = "ripe-geo-autoupdate")]
                 let from_url = {
-                    let uri = autoupdate.uri().ok_or(GeoError::RipeGeoConfigNoPath)?;
-                    let result = RipeGeoImpl::from_uri(uri, *overlaps, None);
+                    let uri = autoupdate.location().ok_or(GeoError::RipeGeoConfigNoPath)?;
+                    if let Some(cache_dir) = cache_dir {
+                        let max_unpacked_size =
+                            autoupdate.download_options().limits().max_unpacked_size();
+                        match RipeGeoImpl::from_cache(cache_dir, *overlaps, max_unpacked_size) {
+                            Ok(ripe_geo_impl) => {
+                                log::info!("ripe-geo database is loaded from cache {cache_dir:?}");
+                                return Ok((ripe_geo_impl, RipeGeoSource::Cache));
+                            }
+                            // Cache is empty at the first start, so it is not a warning
+                            Err(err) => log::info!(
+                                "Cannot load ripe-geo database from cache {cache_dir:?}: {err}"
+                            ),
+                        }
+                    }
+                    let result = RipeGeoImpl::from_location(
+                        uri,
+                        *overlaps,
+                        &autoupdate
+                            .download_options()
+                            .with_cache_dir(cache_dir.clone()),
+                        None,
+                    );
                     if result.is_ok() {
                         log::info!("ripe-geo database is loaded from {uri}")
                     }
-                    result
+                    result.map(|(ripe_geo_impl, validators)| {
+                        (ripe_geo_impl, RipeGeoSource::Uri(validators))
+                    })
                 };
                 #[cfg(feature = "ripe-geo-embedded")]
                 {
                     #[cfg(feature = "ripe-geo-autoupdate")]
-                    if let Ok(ripe_geo_impl) = from_url {
-                        return Ok(ripe_geo_impl);
+                    if let Ok(value) = from_url {
+                        return Ok(value);
                     }
                     let ripe_geo_impl = RipeGeoImpl::from_embedded();
                     log::info!("ripe-geo database is loaded from embedded");
-                    return Ok(ripe_geo_impl);
+                    return Ok((ripe_geo_impl, RipeGeoSource::Embedded));
                 }
                 #[cfg(feature = "ripe-geo-autoupdate")]
                 return from_url.map_err(Into::into);
@@ -72,11 +111,39 @@ impl TryInto<RipeGeo> for RipeGeoConfig {
     type Error = GeoError;
 
     fn try_into(self) -> Result<RipeGeo, Self::Error> {
+        // Take the stamp before loading, so changes made while loading are not missed
+        #[cfg(feature = "ripe-geo-autoupdate")]
+        let path_validators = match (&self.path, self.autoupdate.reads_path()) {
+            (Some(path), true) => ArchiveValidators::local(path).unwrap_or_default(),
+            (None, true) => return Err(GeoError::RipeGeoConfigNoPath),
+            (_, false) => ArchiveValidators::default(),
+        };
+        // source could be unused
+        #[allow(unused_variables)]
+        let (ripe_geo_impl, source) = self.ripe_geo_impl()?;
         #[allow(unused_mut)]
-        let mut ripe_geo: RipeGeo = self.ripe_geo_impl()?.into();
+        let mut ripe_geo: RipeGeo = ripe_geo_impl.into();
         #[cfg(feature = "ripe-geo-autoupdate")]
         {
-            ripe_geo.set_updater(self.autoupdate.into_updater())
+            // Cached database could be outdated, refresh it in background
+            let immediate_update = matches!(source, RipeGeoSource::Cache);
+            let validators = match source {
+                RipeGeoSource::Uri(validators) => validators,
+                RipeGeoSource::Path => path_validators,
+                _ => ArchiveValidators::default(),
+            };
+            let path = self.path;
+            let updater = self.autoupdate.into_updater().map(|updater| {
+                let updater = match (updater.reads_path(), path) {
+                    (true, Some(path)) => updater.with_location(ArchiveLocation::Local(path)),
+                    _ => updater,
+                };
+                updater
+                    .with_cache_dir(self.cache_dir)
+                    .with_validators(validators)
+                    .with_immediate_update(immediate_update)
+            });
+            ripe_geo.set_updater(updater)
         }
         Ok(ripe_geo)
     }
@@ -87,7 +154,7 @@ impl TryInto<RipeGeo> for RipeGeoConfig {
 #[serde(untagged)]
 enum RipeGeoAutoupdateConfig {
     Boolean(bool),
-    Updater(RipeGeoUpdater),
+    Updater(Box<RipeGeoUpdater>),
 }
 
 #[cfg(feature = "ripe-geo-autoupdate")]
@@ -99,11 +166,25 @@ impl Default for RipeGeoAutoupdateConfig {
 
 #[cfg(feature = "ripe-geo-autoupdate")]
 impl RipeGeoAutoupdateConfig {
-    fn uri(&self) -> Option<&Uri> {
+    fn location(&self) -> Option<&ArchiveLocation> {
         match self {
             Self::Boolean(false) => None,
-            Self::Boolean(true) => Some(RipeGeoUpdater::default_uri_ref()),
-            Self::Updater(updater) => Some(updater.uri()),
+            Self::Boolean(true) => Some(RipeGeoUpdater::default_location_ref()),
+            Self::Updater(updater) => Some(updater.location()),
+        }
+    }
+
+    fn reads_path(&self) -> bool {
+        match self {
+            Self::Boolean(_) => false,
+            Self::Updater(updater) => updater.reads_path(),
+        }
+    }
+
+    fn download_options(&self) -> RipeGeoDownloadOptions {
+        match self {
+            Self::Boolean(_) => RipeGeoDownloadOptions::default(),
+            Self::Updater(updater) => updater.options().clone(),
         }
     }
 
@@ -111,10 +192,16 @@ impl RipeGeoAutoupdateConfig {
         match self {
             Self::Boolean(false) => None,
             Self::Boolean(true) => Some(RipeGeoUpdater::default()),
-            Self::Updater(updater) => Some(updater),
+            Self::Updater(updater) => Some(*updater),
         }
     }
 }
 
 #[cfg(not(feature = "ripe-geo-autoupdate"))]
 type RipeGeoAutoupdateConfig = Unavailable;
+
+#[cfg(feature = "ripe-geo-autoupdate")]
+type RipeGeoCacheDir = PathBuf;
+
+#[cfg(not(feature = "ripe-geo-autoupdate"))]
+type RipeGeoCacheDir = Unavailable;
@@ -1,14 +1,25 @@
 use super::*;
 
-#[cfg(feature = "ripe-geo-embedded")]
+#[cfg(not(ripe_geo_embedded_snapshot))]
 use include_dir::include_dir;
 
-#[cfg(feature = "ripe-geo-embedded")]
+#[cfg(ripe_geo_embedded_snapshot_missing)]
+compile_error!(
+    "ripe-geo-embedded-snapshot feature requires GEO302_RIPE_GEO_SNAPSHOT environment variable \
+    with the path of a file made by `geo302 db compile` for release builds"
+);
+
+#[cfg(not(ripe_geo_embedded_snapshot))]
 const RIPE_GEO_CONTINENTS_DIR: include_dir::Dir<'_> =
     include_dir!("$CARGO_MANIFEST_DIR/ripe-geo/continents");
 
+/// Output of `geo302 db compile`, its path is resolved by build.rs
+#[cfg(ripe_geo_embedded_snapshot)]
+const RIPE_GEO_SNAPSHOT: &[u8] = include_bytes!(env!("GEO302_RIPE_GEO_SNAPSHOT_PATH"));
+
 impl RipeGeoImpl {
     // Since all errors here are related to compile-time issues, we don't need Result and just panic
+    #[cfg(not(ripe_geo_embedded_snapshot))]
     pub fn from_embedded() -> Self {
         let it = RIPE_GEO_CONTINENTS_DIR.files().map(|file| {
             let reader: Box<dyn Read> = Box::new(file.contents());
@@ -17,4 +28,11 @@ impl RipeGeoImpl {
         Self::from_text_files(it, RipeGeoOverlapsStrategy::Skip)
             .expect("Recompile geo302 with correct ripe-geo/continents folder embedded")
     }
+
+    // Since all errors here are related to compile-time issues, we don't need Result and just panic
+    #[cfg(ripe_geo_embedded_snapshot)]
+    pub fn from_embedded() -> Self {
+        Self::from_snapshot(RIPE_GEO_SNAPSHOT)
+            .expect("Recompile geo302 with a snapshot made by the same geo302 version embedded")
+    }
 }
@@ -0,0 +1,134 @@
+use super::updater::RipeGeoDownloadError;
+use super::*;
+use crate::download::FileStamp;
+
+use hyper::body::Bytes;
+use hyper::http::uri::Uri;
+
+/// Where to get a ripe-geo archive or a related file from
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "String")]
+pub enum ArchiveLocation {
+    /// HTTP(S) URL
+    Web(Uri),
+    /// Absolute path of a local archive or "continents" directory, given as file:// URL
+    Local(PathBuf),
+}
+
+impl ArchiveLocation {
+    /// Location of a file with the same name and the suffix appended, like "archive.tar.gz.minisig"
+    pub fn with_suffix(&self, suffix: &str) -> Self {
+        match self {
+            Self::Web(uri) => Self::Web(
+                format!("{uri}{suffix}")
+                    .parse()
+                    .expect("URI with appended suffix must be valid"),
+            ),
+            Self::Local(path) => {
+                let mut path = path.clone().into_os_string();
+                path.push(suffix);
+                Self::Local(path.into())
+            }
+        }
+    }
+}
+
+impl TryFrom<String> for ArchiveLocation {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        if let Some(rest) = s.strip_prefix("file://") {
+            // Only local host is supported, so "file:///path" and "file://localhost/path" are the
+            // same
+            let path = rest.strip_prefix("localhost").unwrap_or(rest);
+            if !path.starts_with('/') {
+                return Err(format!(r#"file URL "{s}" must have an absolute path"#));
+            }
+            return Ok(Self::Local(path.into()));
+        }
+        let uri: Uri = s
+            .parse()
+            .map_err(|e| format!(r#"invalid URL "{s}": {e}"#))?;
+        match uri.scheme_str() {
+            Some("http" | "https") if uri.host().is_some() => Ok(Self::Web(uri)),
+            _ => Err(format!(
+                r#"URL "{s}" must have http, https or file scheme and a host for http(s)"#
+            )),
+        }
+    }
+}
+
+impl std::fmt::Display for ArchiveLocation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Web(uri) => write!(f, "{uri}"),
+            Self::Local(path) => write!(f, "file://{}", path.display()),
+        }
+    }
+}
+
+/// Downloaded archive or local directory
+#[derive(Debug)]
+pub enum ArchiveContent {
+    Archive(Bytes),
+    Directory(PathBuf),
+}
+
+/// Read local archive or check local directory, `None` is returned if its stamp equals `current`
+pub fn read_local(
+    path: &Path,
+    current: Option<FileStamp>,
+    max_size: u64,
+) -> Result<Option<(ArchiveContent, FileStamp)>, RipeGeoDownloadError> {
+    let stamp = FileStamp::new(path)?;
+    if Some(stamp) == current {
+        return Ok(None);
+    }
+    if path.is_dir() {
+        return Ok(Some((ArchiveContent::Directory(path.to_owned()), stamp)));
+    }
+    if stamp.size() > max_size {
+        return Err(RipeGeoDownloadError::TooLarge(max_size));
+    }
+    let archive = std::fs::read(path)?;
+    if archive.len() as u64 > max_size {
+        return Err(RipeGeoDownloadError::TooLarge(max_size));
+    }
+    Ok(Some((ArchiveContent::Archive(archive.into()), stamp)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse() {
+        let location: ArchiveLocation = "file:///srv/ripe-geo.zip".to_owned().try_into().unwrap();
+        assert_eq!(location, ArchiveLocation::Local("/srv/ripe-geo.zip".into()));
+        assert_eq!(location.to_string(), "file:///srv/ripe-geo.zip");
+        assert_eq!(
+            location.with_suffix(".sha256"),
+            ArchiveLocation::Local("/srv/ripe-geo.zip.sha256".into())
+        );
+        let location: ArchiveLocation = "file://localhost/srv/continents"
+            .to_owned()
+            .try_into()
+            .unwrap();
+        assert_eq!(location, ArchiveLocation::Local("/srv/continents".into()));
+        let location: ArchiveLocation = "https://example.org/continents.tar.gz"
+            .to_owned()
+            .try_into()
+            .unwrap();
+        assert_eq!(
+            location.with_suffix(".minisig").to_string(),
+            "https://example.org/continents.tar.gz.minisig"
+        );
+        for s in [
+            "file://relative/path",
+            "ftp://example.org/continents.tar.gz",
+            "/srv/ripe-geo.zip",
+        ] {
+            assert!(ArchiveLocation::try_from(s.to_owned()).is_err(), "{s}");
+        }
+    }
+}
@@ -2,7 +2,7 @@ use crate::geo::{Continent, GeoError, GeoTrait};
 use crate::intervals::{IntervalBTreeMap, IntervalVec, Intervals};
 
 use serde::Deserialize;
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
 use std::fmt::Debug;
 use std::io::{BufRead, BufReader, Read};
 use std::net::{AddrParseError, IpAddr, Ipv4Addr, Ipv6Addr};
@@ -13,26 +13,30 @@ use std::str::{FromStr, Utf8Error};
 use std::sync::{Arc, RwLock};
 use thiserror::Error;
 
+#[cfg(feature = "ripe-geo-autoupdate")]
+pub mod archive;
 pub mod config;
 #[cfg(feature = "ripe-geo-embedded")]
 pub mod embedded;
 #[cfg(feature = "ripe-geo-autoupdate")]
+pub mod location;
+#[cfg(feature = "ripe-geo-autoupdate")]
+pub mod sanity;
+pub mod snapshot;
+#[cfg(feature = "ripe-geo-autoupdate")]
 pub mod updater;
+#[cfg(feature = "ripe-geo-autoupdate")]
+pub mod verification;
 
-#[derive(Copy, Clone, Deserialize, Debug)]
+#[derive(Copy, Clone, Deserialize, Debug, Default)]
 pub enum RipeGeoOverlapsStrategy {
     #[serde(alias = "fail")]
     Fail,
     #[serde(alias = "skip")]
+    #[default]
     Skip,
 }
 
-impl Default for RipeGeoOverlapsStrategy {
-    fn default() -> Self {
-        Self::Skip
-    }
-}
-
 #[derive(Error, Debug)]
 pub enum RipeGeoDataError {
     #[error(r#"Error parsing file "{path}": {error}"#)]
@@ -53,15 +57,22 @@ pub enum RipeGeoDataError {
         error: std::io::Error,
     },
     #[cfg(feature = "ripe-geo-autoupdate")]
-    #[error(r#"Error while reading file "{path}" of tar.gz archive: {error}"#)]
+    #[error(r#"Error while reading file "{path}" of archive: {error}"#)]
     ArchiveEntryIoError {
         path: PathBuf,
         error: std::io::Error,
     },
     #[cfg(feature = "ripe-geo-autoupdate")]
-    #[error(r"Error while reading tar.gz file: {0}")]
+    #[error(r"Error while reading archive: {0}")]
     ArchiveReadError(std::io::Error),
     #[cfg(feature = "ripe-geo-autoupdate")]
+    #[error(
+        "Archive format is not recognised, tar, tar.gz, tar.zst, zip and geo302 snapshots are supported"
+    )]
+    UnknownArchiveFormat,
+    #[error(transparent)]
+    Snapshot(#[from] snapshot::RipeGeoSnapshotError),
+    #[cfg(feature = "ripe-geo-autoupdate")]
     #[error(transparent)]
     DownloadError(#[from] updater::RipeGeoDownloadError),
 }
@@ -104,6 +115,7 @@ pub enum IpType {
 trait IpTypeTrait {
     type Addr: FromStr<Err = AddrParseError> + From<Self::UInt> + Copy + std::fmt::Debug;
     type UInt: From<Self::Addr>
+        + Into<u128>
         + Copy
         + Ord
         + std::fmt::Debug
@@ -191,12 +203,13 @@ where
     }
 }
 
-impl<Ip> ToString for Record<Ip>
+impl<Ip> std::fmt::Display for Record<Ip>
 where
     Ip: IpTypeTrait,
 {
-    fn to_string(&self) -> String {
-        format!(
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
             "{subnet:?}/{suffix:?}",
             subnet = self.subnet,
             suffix = Ip::suffix_from_size(self.size).expect("size must be power of two")
@@ -217,7 +230,7 @@ const ALL_RIPE_GEO_CONTINENTS: [Continent; 6] = [
 pub struct RipeGeo {
     inner: Arc<RwLock<RipeGeoImpl>>,
     overlaps_strategy: RipeGeoOverlapsStrategy,
-    updater: Option<RwLock<updater::RipeGeoUpdater>>,
+    updater: Option<Box<RwLock<updater::RipeGeoUpdater>>>,
 }
 
 #[cfg(not(feature = "ripe-geo-autoupdate"))]
@@ -270,9 +283,33 @@ impl GeoTrait for RipeGeo {
     }
 }
 
+/// Number of records and covered addresses per continent and IP family
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RipeGeoStats {
+    records: HashMap<(Continent, IpType), usize>,
+    coverage: HashMap<(Continent, IpType), u128>,
+}
+
+impl RipeGeoStats {
+    pub fn records(&self, continent: Continent, ip: IpType) -> usize {
+        self.records.get(&(continent, ip)).copied().unwrap_or(0)
+    }
+
+    pub fn coverage(&self, continent: Continent, ip: IpType) -> u128 {
+        self.coverage.get(&(continent, ip)).copied().unwrap_or(0)
+    }
+
+    fn add(&mut self, continent: Continent, ip: IpType, records: usize, coverage: u128) {
+        *self.records.entry((continent, ip)).or_default() += records;
+        let total = self.coverage.entry((continent, ip)).or_default();
+        *total = total.saturating_add(coverage);
+    }
+}
+
 pub struct RipeGeoImpl {
     ipv4: IntervalVec<u32, Continent>,
     ipv6: IntervalVec<u128, Continent>,
+    stats: RipeGeoStats,
 }
 
 impl RipeGeoImpl {
@@ -288,18 +325,21 @@ impl RipeGeoImpl {
         Some((continent, ip))
     }
 
+    /// Insert file records into the tree, return warnings, number of inserted records and number
+    /// of covered addresses
     fn insert_file<Ip>(
         tree: &mut IntervalBTreeMap<Ip::UInt, Continent>,
         reader: Box<dyn Read>,
         continent: Continent,
         overlaps_strategy: RipeGeoOverlapsStrategy,
-    ) -> Result<Vec<RipeGeoFileError>, RipeGeoFileError>
+    ) -> Result<(Vec<RipeGeoFileError>, usize, u128), RipeGeoFileError>
     where
         Ip: IpTypeTrait,
     {
         let buf_reader = BufReader::new(reader);
         let mut warnings = vec![];
         let mut count = 0;
+        let mut coverage: u128 = 0;
         for line in buf_reader.lines() {
             let line = line?;
             let record: Record<Ip> =
@@ -313,7 +353,7 @@ impl RipeGeoImpl {
                 let error = RipeGeoFileError::OverlappedRecord(
                     record.to_string(),
                     Record::<Ip> {
-                        subnet: error.key.into(),
+                        subnet: Ip::Addr::from(error.key),
                         size: error.size,
                     }
                     .to_string(),
@@ -324,12 +364,13 @@ impl RipeGeoImpl {
                 }
             } else {
                 count += 1;
+                coverage = coverage.saturating_add(record.size.into());
             }
         }
         if count == 0 {
             Err(RipeGeoFileError::EmptyFile)
         } else {
-            Ok(warnings)
+            Ok((warnings, count, coverage))
         }
     }
 
@@ -343,6 +384,7 @@ impl RipeGeoImpl {
     {
         let mut ipv4 = IntervalBTreeMap::new();
         let mut ipv6 = IntervalBTreeMap::new();
+        let mut stats = RipeGeoStats::default();
         let mut cont_ip_set = {
             let mut set = HashSet::new();
             for continent in ALL_RIPE_GEO_CONTINENTS {
@@ -364,7 +406,7 @@ impl RipeGeoImpl {
                 error,
                 path: path.to_owned(),
             };
-            match ip {
+            let (warnings, records, coverage) = match ip {
                 IpType::V4 => {
                     Self::insert_file::<IpV4>(&mut ipv4, reader, continent, overlaps_strategy)
                 }
@@ -372,18 +414,26 @@ impl RipeGeoImpl {
                     Self::insert_file::<IpV6>(&mut ipv6, reader, continent, overlaps_strategy)
                 }
             }
-            .map_err(error_mapper)?
-            .into_iter()
-            .map(error_mapper)
-            .for_each(|warning| log::warn!("{warning}"));
+            .map_err(error_mapper)?;
+            warnings
+                .into_iter()
+                .map(error_mapper)
+                .for_each(|warning| log::warn!("{warning}"));
+            stats.add(continent, ip, records, coverage);
         }
         if !cont_ip_set.is_empty() {
             return Err(RipeGeoDataError::MissingFiles(cont_ip_set));
         }
-        Ok(Self {
-            ipv4: ipv4.into(),
-            ipv6: ipv6.into(),
-        })
+        // ripe-geo has many adjacent subnets of the same continent
+        let mut ipv4: IntervalVec<_, _> = ipv4.into();
+        ipv4.coalesce();
+        let mut ipv6: IntervalVec<_, _> = ipv6.into();
+        ipv6.coalesce();
+        Ok(Self { ipv4, ipv6, stats })
+    }
+
+    pub fn stats(&self) -> &RipeGeoStats {
+        &self.stats
     }
 
     pub fn from_folder(
@@ -423,6 +473,30 @@ impl RipeGeoImpl {
         Self::from_text_files(it, overlaps_strategy)
     }
 
+    /// Load a directory of text lists, or a file which is a snapshot made by `geo302 db compile`
+    /// (or any supported archive if autoupdate is enabled)
+    pub fn from_path(
+        path: &Path,
+        overlaps_strategy: RipeGeoOverlapsStrategy,
+    ) -> Result<Self, RipeGeoDataError> {
+        if path.is_dir() {
+            return Self::from_folder(path, overlaps_strategy);
+        }
+        let data = std::fs::read(path).map_err(|error| RipeGeoDataError::FileIoError {
+            error,
+            path: path.to_owned(),
+        })?;
+        #[cfg(feature = "ripe-geo-autoupdate")]
+        {
+            let max_unpacked_size = updater::RipeGeoDownloadLimits::default().max_unpacked_size();
+            Self::from_archive(&data, overlaps_strategy, max_unpacked_size)
+        }
+        #[cfg(not(feature = "ripe-geo-autoupdate"))]
+        {
+            Ok(Self::from_snapshot(&data)?)
+        }
+    }
+
     pub fn into_interval_btree_maps(
         self,
     ) -> (
@@ -0,0 +1,235 @@
+use super::*;
+
+use serde::Deserializer;
+use std::collections::BTreeMap;
+
+#[derive(Debug, Error)]
+pub enum RipeGeoSanityError {
+    #[error("{continent:?} {ip:?} has {records} records, at least {min_records} are required")]
+    TooFewRecords {
+        continent: Continent,
+        ip: IpType,
+        records: usize,
+        min_records: usize,
+    },
+    #[error("{continent:?} {ip:?} coverage changed by {change:.1}%, maximum is {max_change}%")]
+    CoverageChange {
+        continent: Continent,
+        ip: IpType,
+        change: f64,
+        max_change: f64,
+    },
+    #[error(
+        "canary {address} resolves to {} instead of {expected:?}",
+        .actual.map_or("no continent".to_owned(), |continent| format!("{continent:?}"))
+    )]
+    Canary {
+        address: IpAddr,
+        expected: Continent,
+        actual: Option<Continent>,
+    },
+}
+
+/// Guards a new database must pass before it replaces the current one
+#[derive(Deserialize, Debug, Clone)]
+pub struct RipeGeoSanityChecks {
+    /// Minimum number of records per continent and IP family
+    #[serde(default = "RipeGeoSanityChecks::default_min_records")]
+    min_records: usize,
+    /// Maximum change of the number of covered addresses per continent and IP family versus the
+    /// current database, in percent
+    #[serde(default)]
+    max_coverage_change: Option<f64>,
+    /// Addresses with their expected continents
+    #[serde(default, deserialize_with = "deserialize_canaries")]
+    canaries: BTreeMap<IpAddr, Continent>,
+}
+
+impl Default for RipeGeoSanityChecks {
+    fn default() -> Self {
+        Self {
+            min_records: Self::default_min_records(),
+            max_coverage_change: None,
+            canaries: BTreeMap::new(),
+        }
+    }
+}
+
+fn deserialize_canaries<'de, D>(deserializer: D) -> Result<BTreeMap<IpAddr, Continent>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let canaries: BTreeMap<IpAddr, String> = Deserialize::deserialize(deserializer)?;
+    canaries
+        .into_iter()
+        .map(|(address, continent)| {
+            let continent = Continent::try_from(continent.as_str()).map_err(|_| {
+                serde::de::Error::custom(format!(r#"canary continent "{continent}" is unknown"#))
+            })?;
+            Ok((address, continent))
+        })
+        .collect()
+}
+
+impl RipeGeoSanityChecks {
+    fn default_min_records() -> usize {
+        1
+    }
+
+    /// Check `new` database, `current` is the stats of the database it is going to replace
+    pub fn check(
+        &self,
+        new: &RipeGeoImpl,
+        current: Option<&RipeGeoStats>,
+    ) -> Result<(), RipeGeoSanityError> {
+        for continent in ALL_RIPE_GEO_CONTINENTS {
+            for ip in [IpType::V4, IpType::V6] {
+                self.check_records(new.stats(), continent, ip)?;
+                if let (Some(max_change), Some(current)) = (self.max_coverage_change, current) {
+                    Self::check_coverage(new.stats(), current, continent, ip, max_change)?;
+                }
+            }
+        }
+        for (&address, &expected) in &self.canaries {
+            let actual = new.try_lookup_continent(address).ok();
+            if actual != Some(expected) {
+                return Err(RipeGeoSanityError::Canary {
+                    address,
+                    expected,
+                    actual,
+                });
+            }
+        }
+        Ok(())
+    }
+
+    fn check_records(
+        &self,
+        stats: &RipeGeoStats,
+        continent: Continent,
+        ip: IpType,
+    ) -> Result<(), RipeGeoSanityError> {
+        let records = stats.records(continent, ip);
+        if records < self.min_records {
+            return Err(RipeGeoSanityError::TooFewRecords {
+                continent,
+                ip,
+                records,
+                min_records: self.min_records,
+            });
+        }
+        Ok(())
+    }
+
+    fn check_coverage(
+        new: &RipeGeoStats,
+        current: &RipeGeoStats,
+        continent: Continent,
+        ip: IpType,
+        max_change: f64,
+    ) -> Result<(), RipeGeoSanityError> {
+        let current_coverage = current.coverage(continent, ip);
+        if current_coverage == 0 {
+            return Ok(());
+        }
+        let new_coverage = new.coverage(continent, ip);
+        let change =
+            (new_coverage as f64 - current_coverage as f64).abs() / current_coverage as f64 * 100.0;
+        if change > max_change {
+            return Err(RipeGeoSanityError::CoverageChange {
+                continent,
+                ip,
+                change,
+                max_change,
+            });
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    /// Database with a single record per continent and IP family, Europe IPv4 has `europe_ipv4`
+    fn ripe_geo_impl(europe_ipv4: &[&str]) -> RipeGeoImpl {
+        let files = [
+            ("africa", vec!["41.0.0.0/8"], "2c0f::/16"),
+            ("asia", vec!["1.0.0.0/8"], "2400::/12"),
+            ("europe", europe_ipv4.to_vec(), "2a00::/12"),
+            ("north-america", vec!["3.0.0.0/8"], "2600::/12"),
+            ("oceania", vec!["4.0.0.0/8"], "2001::/16"),
+            ("south-america", vec!["5.0.0.0/8"], "2800::/12"),
+        ];
+        let it = files.into_iter().flat_map(|(continent, ipv4, ipv6)| {
+            [
+                (format!("{continent}.ipv4.list"), ipv4.join("\n")),
+                (format!("{continent}.ipv6.list"), ipv6.to_owned()),
+            ]
+            .into_iter()
+            .map(|(path, content)| {
+                let reader: Box<dyn Read> = Box::new(Cursor::new(content));
+                Ok((PathBuf::from(path), reader))
+            })
+        });
+        RipeGeoImpl::from_text_files(it, RipeGeoOverlapsStrategy::Fail).unwrap()
+    }
+
+    #[test]
+    fn min_records() {
+        let new = ripe_geo_impl(&["2.0.0.0/8", "6.0.0.0/8"]);
+        let checks: RipeGeoSanityChecks = toml::from_str("min_records = 2").unwrap();
+        assert!(matches!(
+            checks.check(&new, None),
+            Err(RipeGeoSanityError::TooFewRecords {
+                records: 1,
+                min_records: 2,
+                ..
+            })
+        ));
+        assert!(RipeGeoSanityChecks::default().check(&new, None).is_ok());
+    }
+
+    #[test]
+    fn coverage_change() {
+        let current = ripe_geo_impl(&["2.0.0.0/8"]);
+        let checks: RipeGeoSanityChecks = toml::from_str("max_coverage_change = 60").unwrap();
+        // +50%
+        let new = ripe_geo_impl(&["2.0.0.0/8", "6.0.0.0/9"]);
+        assert!(checks.check(&new, Some(current.stats())).is_ok());
+        // -50%
+        let new = ripe_geo_impl(&["2.0.0.0/9"]);
+        assert!(checks.check(&new, Some(current.stats())).is_ok());
+        // +100%
+        let new = ripe_geo_impl(&["2.0.0.0/8", "6.0.0.0/8"]);
+        assert!(matches!(
+            checks.check(&new, Some(current.stats())),
+            Err(RipeGeoSanityError::CoverageChange {
+                continent: Continent::Europe,
+                ip: IpType::V4,
+                ..
+            })
+        ));
+        // No current database to compare with
+        assert!(checks.check(&new, None).is_ok());
+    }
+
+    #[test]
+    fn canaries() {
+        let new = ripe_geo_impl(&["2.0.0.0/8"]);
+        let checks: RipeGeoSanityChecks =
+            toml::from_str(r#"canaries = { "2.3.4.5" = "Europe", "2a00::1" = "Europe" }"#).unwrap();
+        assert!(checks.check(&new, None).is_ok());
+        let checks: RipeGeoSanityChecks =
+            toml::from_str(r#"canaries = { "8.8.8.8" = "NorthAmerica" }"#).unwrap();
+        assert!(matches!(
+            checks.check(&new, None),
+            Err(RipeGeoSanityError::Canary { actual: None, .. })
+        ));
+        assert!(
+            toml::from_str::<RipeGeoSanityChecks>(r#"canaries = { "8.8.8.8" = "Atlantis" }"#)
+                .is_err()
+        );
+    }
+}
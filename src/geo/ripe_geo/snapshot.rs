@@ -0,0 +1,314 @@
+use super::*;
+use crate::intervals::CheckedAdd;
+
+/// Magic bytes at the start of every snapshot
+const SNAPSHOT_MAGIC: &[u8; 8] = b"GEO302RG";
+
+/// Incremented on every incompatible change of the layout
+const SNAPSHOT_VERSION: u32 = 1;
+
+#[derive(Error, Debug)]
+pub enum RipeGeoSnapshotError {
+    #[error("Not a geo302 ripe-geo snapshot")]
+    Magic,
+    #[error("Unsupported snapshot version {0}, only version {SNAPSHOT_VERSION} is supported")]
+    Version(u32),
+    #[error("Snapshot is truncated")]
+    Truncated,
+    #[error("Snapshot has {0} unexpected trailing bytes")]
+    Trailing(usize),
+    #[error("Unknown continent code {0}")]
+    Continent(u8),
+    #[error("Unknown IP family code {0}")]
+    IpType(u8),
+    #[error("Intervals are empty, overlapping, not sorted or out of range")]
+    InvalidIntervals,
+}
+
+fn continent_to_code(continent: Continent) -> u8 {
+    match continent {
+        Continent::Africa => 0,
+        Continent::Asia => 1,
+        Continent::Europe => 2,
+        Continent::NorthAmerica => 3,
+        Continent::Oceania => 4,
+        Continent::SouthAmerica => 5,
+        Continent::Antarctica => 6,
+        Continent::Default => 7,
+    }
+}
+
+fn continent_from_code(code: u8) -> Result<Continent, RipeGeoSnapshotError> {
+    match code {
+        0 => Ok(Continent::Africa),
+        1 => Ok(Continent::Asia),
+        2 => Ok(Continent::Europe),
+        3 => Ok(Continent::NorthAmerica),
+        4 => Ok(Continent::Oceania),
+        5 => Ok(Continent::SouthAmerica),
+        6 => Ok(Continent::Antarctica),
+        7 => Ok(Continent::Default),
+        _ => Err(RipeGeoSnapshotError::Continent(code)),
+    }
+}
+
+/// Fixed-width little-endian integers the snapshot is made of
+trait SnapshotInt: Copy {
+    const SIZE: usize;
+
+    fn write(self, buf: &mut Vec<u8>);
+
+    /// `bytes` must be exactly [SnapshotInt::SIZE] long
+    fn read(bytes: &[u8]) -> Self;
+}
+
+macro_rules! impl_snapshot_int {
+    ($($t: ty),*) => {
+        $(
+            impl SnapshotInt for $t {
+                const SIZE: usize = std::mem::size_of::<$t>();
+
+                fn write(self, buf: &mut Vec<u8>) {
+                    buf.extend_from_slice(&self.to_le_bytes());
+                }
+
+                fn read(bytes: &[u8]) -> Self {
+                    Self::from_le_bytes(bytes.try_into().expect("slice has the integer size"))
+                }
+            }
+        )*
+    };
+}
+
+impl_snapshot_int!(u8, u32, u64, u128);
+
+struct SnapshotReader<'a> {
+    buf: &'a [u8],
+}
+
+impl<'a> SnapshotReader<'a> {
+    fn take(&mut self, size: usize) -> Result<&'a [u8], RipeGeoSnapshotError> {
+        if size > self.buf.len() {
+            return Err(RipeGeoSnapshotError::Truncated);
+        }
+        let (head, tail) = self.buf.split_at(size);
+        self.buf = tail;
+        Ok(head)
+    }
+
+    fn read<T: SnapshotInt>(&mut self) -> Result<T, RipeGeoSnapshotError> {
+        Ok(T::read(self.take(T::SIZE)?))
+    }
+
+    fn read_len(&mut self) -> Result<usize, RipeGeoSnapshotError> {
+        // Larger lengths would fail with Truncated anyway
+        usize::try_from(self.read::<u64>()?).map_err(|_| RipeGeoSnapshotError::Truncated)
+    }
+
+    fn read_vec<T: SnapshotInt>(&mut self, len: usize) -> Result<Vec<T>, RipeGeoSnapshotError> {
+        let size = len
+            .checked_mul(T::SIZE)
+            .ok_or(RipeGeoSnapshotError::Truncated)?;
+        Ok(self
+            .take(size)?
+            .chunks_exact(T::SIZE)
+            .map(T::read)
+            .collect())
+    }
+
+    /// Keys, then sizes, then continent codes of all intervals
+    fn read_intervals<T>(&mut self) -> Result<IntervalVec<T, Continent>, RipeGeoSnapshotError>
+    where
+        T: SnapshotInt + CheckedAdd + std::ops::Add<T, Output = T> + Ord + std::fmt::Debug,
+    {
+        let len = self.read_len()?;
+        let keys = self.read_vec::<T>(len)?;
+        let sizes = self.read_vec::<T>(len)?;
+        let continents = self
+            .take(len)?
+            .iter()
+            .map(|&code| continent_from_code(code))
+            .collect::<Result<Vec<_>, _>>()?;
+        IntervalVec::from_sorted(keys, sizes.into_iter().zip(continents).collect())
+            .ok_or(RipeGeoSnapshotError::InvalidIntervals)
+    }
+}
+
+fn write_intervals<T>(buf: &mut Vec<u8>, intervals: &IntervalVec<T, Continent>)
+where
+    T: SnapshotInt + std::ops::Add<T, Output = T> + Ord + std::fmt::Debug,
+{
+    (intervals.len() as u64).write(buf);
+    for (key, _) in intervals.iter() {
+        key.write(buf);
+    }
+    for (_, (size, _)) in intervals.iter() {
+        size.write(buf);
+    }
+    for (_, (_, continent)) in intervals.iter() {
+        continent_to_code(*continent).write(buf);
+    }
+}
+
+impl RipeGeoImpl {
+    pub fn is_snapshot(data: &[u8]) -> bool {
+        data.starts_with(SNAPSHOT_MAGIC)
+    }
+
+    /// Serialize parsed database, so it can be loaded without parsing text lists
+    pub fn to_snapshot(&self) -> Vec<u8> {
+        let mut buf = SNAPSHOT_MAGIC.to_vec();
+        SNAPSHOT_VERSION.write(&mut buf);
+
+        let mut stats = self.stats.records.keys().collect::<Vec<_>>();
+        // Make output reproducible
+        stats.sort_by_key(|(continent, ip)| (continent_to_code(*continent), *ip as u8));
+        (stats.len() as u64).write(&mut buf);
+        for &(continent, ip) in stats {
+            continent_to_code(continent).write(&mut buf);
+            (ip as u8).write(&mut buf);
+            (self.stats.records(continent, ip) as u64).write(&mut buf);
+            self.stats.coverage(continent, ip).write(&mut buf);
+        }
+
+        write_intervals(&mut buf, &self.ipv4);
+        write_intervals(&mut buf, &self.ipv6);
+        buf
+    }
+
+    pub fn from_snapshot(snapshot: &[u8]) -> Result<Self, RipeGeoSnapshotError> {
+        let mut reader = SnapshotReader { buf: snapshot };
+        if reader
+            .take(SNAPSHOT_MAGIC.len())
+            .map_or(true, |magic| magic != SNAPSHOT_MAGIC)
+        {
+            return Err(RipeGeoSnapshotError::Magic);
+        }
+        let version: u32 = reader.read()?;
+        if version != SNAPSHOT_VERSION {
+            return Err(RipeGeoSnapshotError::Version(version));
+        }
+
+        let mut stats = RipeGeoStats::default();
+        for _ in 0..reader.read_len()? {
+            let continent = continent_from_code(reader.read()?)?;
+            let ip = match reader.read::<u8>()? {
+                0 => IpType::V4,
+                1 => IpType::V6,
+                code => return Err(RipeGeoSnapshotError::IpType(code)),
+            };
+            let records = reader.read::<u64>()? as usize;
+            let coverage = reader.read()?;
+            stats.add(continent, ip, records, coverage);
+        }
+
+        let ipv4 = reader.read_intervals()?;
+        let ipv6 = reader.read_intervals()?;
+        if !reader.buf.is_empty() {
+            return Err(RipeGeoSnapshotError::Trailing(reader.buf.len()));
+        }
+        Ok(Self { ipv4, ipv6, stats })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::io::Cursor;
+
+    fn ripe_geo_impl() -> RipeGeoImpl {
+        let it = ALL_RIPE_GEO_CONTINENTS
+            .into_iter()
+            .enumerate()
+            .flat_map(|(i, continent)| {
+                let name: &str = continent.into();
+                let name = name.to_lowercase().replace(' ', "-");
+                [
+                    (format!("{name}.ipv4.list"), format!("{}.0.0.0/8\n", i + 1)),
+                    (format!("{name}.ipv6.list"), format!("2{i:03x}::/16\n")),
+                ]
+            })
+            .map(|(path, content)| {
+                let reader: Box<dyn Read> = Box::new(Cursor::new(content));
+                Ok((PathBuf::from(path), reader))
+            });
+        RipeGeoImpl::from_text_files(it, RipeGeoOverlapsStrategy::Fail).unwrap()
+    }
+
+    #[test]
+    fn round_trip() {
+        let original = ripe_geo_impl();
+        let snapshot = original.to_snapshot();
+        assert!(RipeGeoImpl::is_snapshot(&snapshot));
+        let loaded = RipeGeoImpl::from_snapshot(&snapshot).unwrap();
+        assert_eq!(loaded.stats(), original.stats());
+        assert_eq!(loaded.to_snapshot(), snapshot);
+        for (address, continent) in [
+            ("3.4.5.6", Some(Continent::Europe)),
+            ("2003::1", Some(Continent::NorthAmerica)),
+            ("10.0.0.1", None),
+        ] {
+            assert_eq!(
+                loaded.try_lookup_continent(address.parse().unwrap()).ok(),
+                continent,
+                "{address}"
+            );
+        }
+    }
+
+    #[test]
+    fn corrupted() {
+        let snapshot = ripe_geo_impl().to_snapshot();
+        assert!(matches!(
+            RipeGeoImpl::from_snapshot(b"asia.ipv4.list"),
+            Err(RipeGeoSnapshotError::Magic)
+        ));
+        assert!(matches!(
+            RipeGeoImpl::from_snapshot(&snapshot[..snapshot.len() - 1]),
+            Err(RipeGeoSnapshotError::Truncated)
+        ));
+        let mut trailing = snapshot.clone();
+        trailing.push(0);
+        assert!(matches!(
+            RipeGeoImpl::from_snapshot(&trailing),
+            Err(RipeGeoSnapshotError::Trailing(1))
+        ));
+        let mut version = snapshot;
+        version[SNAPSHOT_MAGIC.len()] = 0;
+        assert!(matches!(
+            RipeGeoImpl::from_snapshot(&version),
+            Err(RipeGeoSnapshotError::Version(0))
+        ));
+    }
+
+    #[test]
+    fn invalid_intervals() {
+        let snapshot = ripe_geo_impl().to_snapshot();
+        let stats_len = ALL_RIPE_GEO_CONTINENTS.len() * 2;
+        // magic, version, stats length and entries, IPv4 intervals length and keys
+        let ipv4_sizes_offset = SNAPSHOT_MAGIC.len() + 4 + 8 + stats_len * 26 + 8 + 6 * 4;
+        let with_ipv4_size = |index: usize, size: u32| {
+            let mut snapshot = snapshot.clone();
+            let offset = ipv4_sizes_offset + index * 4;
+            snapshot[offset..offset + 4].copy_from_slice(&size.to_le_bytes());
+            RipeGeoImpl::from_snapshot(&snapshot)
+        };
+        assert!(with_ipv4_size(0, 1 << 24).is_ok());
+        // Empty interval
+        assert!(matches!(
+            with_ipv4_size(0, 0),
+            Err(RipeGeoSnapshotError::InvalidIntervals)
+        ));
+        // 1.0.0.0/7 overlaps with 2.0.0.0/8
+        assert!(matches!(
+            with_ipv4_size(0, 2 << 24),
+            Err(RipeGeoSnapshotError::InvalidIntervals)
+        ));
+        // 6.0.0.0 + 251.0.0.0 overflows u32
+        assert!(matches!(
+            with_ipv4_size(5, 251 << 24),
+            Err(RipeGeoSnapshotError::InvalidIntervals)
+        ));
+    }
+}
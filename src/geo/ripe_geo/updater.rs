@@ -1,5 +1,11 @@
 use super::*;
 
+use super::location::{read_local, ArchiveContent, ArchiveLocation};
+use super::sanity::{RipeGeoSanityChecks, RipeGeoSanityError};
+use super::verification::{
+    RipeGeoVerification, RipeGeoVerificationConfig, RipeGeoVerificationError,
+};
+use crate::download::{fetch, write_atomically, DownloadError, FileStamp, HttpValidators};
 use crate::non_zero_duration::NonZeroDuration;
 
 use hyper::body::{Body, Bytes};
@@ -9,7 +15,6 @@ use hyper::http::uri::Uri;
 use hyper::StatusCode;
 use hyper_tls::HttpsConnector;
 use lazy_static::lazy_static;
-use std::io::Cursor;
 use std::time::Duration;
 use tokio::runtime::Handle;
 
@@ -21,8 +26,20 @@ pub enum RipeGeoDownloadError {
     Http(#[from] hyper::http::Error),
     #[error("Non-success status code: {0}")]
     NonSuccess(StatusCode),
-    #[error("Error while unpacking downloaded tar.gz: {0}")]
+    #[error("I/O error while reading archive: {0}")]
     UnpackIo(#[from] std::io::Error),
+    #[error(r#""{0}" is a directory, not a file"#)]
+    NotAFile(PathBuf),
+    #[error(transparent)]
+    Join(#[from] tokio::task::JoinError),
+    #[error("Archive is larger than {0} bytes")]
+    TooLarge(u64),
+    #[error("Download is not finished in {0:?}")]
+    Timeout(Duration),
+    #[error(transparent)]
+    Verification(#[from] RipeGeoVerificationError),
+    #[error("Sanity check failed: {0}")]
+    Sanity(#[from] RipeGeoSanityError),
 }
 
 impl From<StatusCode> for RipeGeoDownloadError {
@@ -31,44 +48,158 @@ impl From<StatusCode> for RipeGeoDownloadError {
     }
 }
 
+impl From<DownloadError> for RipeGeoDownloadError {
+    fn from(error: DownloadError) -> Self {
+        match error {
+            DownloadError::Hyper(error) => Self::Hyper(error),
+            DownloadError::Http(error) => Self::Http(error),
+            DownloadError::NonSuccess(status_code) => Self::NonSuccess(status_code),
+            DownloadError::TooLarge(max_size) => Self::TooLarge(max_size),
+        }
+    }
+}
+
 lazy_static! {
-    static ref RIPE_GEO_URL: Uri =
+    static ref RIPE_GEO_URL: ArchiveLocation = ArchiveLocation::Web(
         "https://github.com/hombit/ripe-geo-history/archive/refs/heads/continents.tar.gz"
             .parse()
-            .unwrap();
+            .unwrap()
+    );
 }
 
 const RIPE_GEO_UPDATE_INTERVAL_SECONDS: u64 = 86400;
 
+const RIPE_GEO_MAX_ARCHIVE_SIZE: u64 = 256 << 20;
+
+/// Unpacked archive files may be this many times larger than the archive size limit
+const RIPE_GEO_MAX_UNPACKED_RATIO: u64 = 8;
+
+const RIPE_GEO_DOWNLOAD_TIMEOUT_SECONDS: u64 = 600;
+
+/// File name of the downloaded archive in the cache directory, the archive format is detected
+/// from the content, so the name is kept for caches written by earlier versions
+const RIPE_GEO_CACHE_FILE: &str = "ripe-geo.tar.gz";
+
+/// Limits applied to every archive download
+#[derive(Debug, Clone, Copy)]
+pub struct RipeGeoDownloadLimits {
+    /// Maximum archive size in bytes
+    max_size: u64,
+    /// Total time of the download, including redirects and body
+    timeout: Duration,
+}
+
+impl RipeGeoDownloadLimits {
+    pub fn with_max_size(self, max_size: u64) -> Self {
+        Self { max_size, ..self }
+    }
+
+    /// Limit of the total size of unpacked archive files, because `max_size` limits the
+    /// compressed archive only
+    pub fn max_unpacked_size(&self) -> u64 {
+        self.max_size.saturating_mul(RIPE_GEO_MAX_UNPACKED_RATIO)
+    }
+}
+
+impl Default for RipeGeoDownloadLimits {
+    fn default() -> Self {
+        Self {
+            max_size: RipeGeoUpdater::default_max_size(),
+            timeout: RipeGeoUpdater::default_timeout().into(),
+        }
+    }
+}
+
+/// How to download, check and store a new database
+#[derive(Debug, Clone, Default)]
+pub struct RipeGeoDownloadOptions {
+    limits: RipeGeoDownloadLimits,
+    verification: RipeGeoVerification,
+    sanity: RipeGeoSanityChecks,
+    /// Directory to store every downloaded archive in
+    cache_dir: Option<PathBuf>,
+}
+
+impl RipeGeoDownloadOptions {
+    pub fn with_cache_dir(self, cache_dir: Option<PathBuf>) -> Self {
+        Self { cache_dir, ..self }
+    }
+
+    pub fn limits(&self) -> &RipeGeoDownloadLimits {
+        &self.limits
+    }
+}
+
+/// Validators of the last downloaded archive, used to make conditional requests, or stamp of the
+/// last read local archive or directory
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ArchiveValidators {
+    http: HttpValidators,
+    local: Option<FileStamp>,
+}
+
+impl ArchiveValidators {
+    /// Validators of local archive or directory
+    pub fn local(path: &Path) -> std::io::Result<Self> {
+        Ok(Self {
+            local: Some(FileStamp::new(path)?),
+            ..Default::default()
+        })
+    }
+}
+
 #[derive(Deserialize, Debug)]
 #[serde(from = "RipeGeoUpdaterConfig")]
 pub struct RipeGeoUpdater {
     interval: Duration,
-    uri: Uri,
+    location: ArchiveLocation,
+    /// Re-read `path` of the database config instead of `location`
+    from_path: bool,
+    options: RipeGeoDownloadOptions,
+    /// Validators of the archive the current database is loaded from
+    validators: ArchiveValidators,
+    /// Update right after start instead of waiting for the first interval
+    immediate_update: bool,
     handle: Option<tokio::task::JoinHandle<()>>,
 }
 
 impl RipeGeoUpdater {
-    pub fn uri(&self) -> &Uri {
-        &self.uri
+    pub fn location(&self) -> &ArchiveLocation {
+        &self.location
+    }
+
+    pub fn reads_path(&self) -> bool {
+        self.from_path
     }
 
-    pub fn default_uri() -> Uri {
+    pub fn default_location() -> ArchiveLocation {
         RIPE_GEO_URL.clone()
     }
 
-    pub fn default_uri_ref() -> &'static Uri {
+    pub fn default_location_ref() -> &'static ArchiveLocation {
         &RIPE_GEO_URL
     }
 
+    pub fn options(&self) -> &RipeGeoDownloadOptions {
+        &self.options
+    }
+
     pub fn default_interval() -> NonZeroDuration {
         NonZeroDuration::from_secs(RIPE_GEO_UPDATE_INTERVAL_SECONDS).unwrap()
     }
+
+    pub fn default_max_size() -> u64 {
+        RIPE_GEO_MAX_ARCHIVE_SIZE
+    }
+
+    pub fn default_timeout() -> NonZeroDuration {
+        NonZeroDuration::from_secs(RIPE_GEO_DOWNLOAD_TIMEOUT_SECONDS).unwrap()
+    }
 }
 
 impl Default for RipeGeoUpdater {
     fn default() -> Self {
-        Self::new(Self::default_interval(), Self::default_uri())
+        Self::new(Self::default_interval(), Self::default_location())
     }
 }
 
@@ -76,33 +207,82 @@ impl Default for RipeGeoUpdater {
 struct RipeGeoUpdaterConfig {
     #[serde(default = "RipeGeoUpdater::default_interval")]
     interval: NonZeroDuration,
-    #[serde(
-        default = "RipeGeoUpdater::default_uri",
-        alias = "url",
-        with = "http_serde::uri"
-    )]
-    uri: Uri,
+    /// http(s) or file URL of tar, tar.gz, tar.zst or zip archive, or file URL of a directory
+    #[serde(default = "RipeGeoUpdater::default_location", alias = "url")]
+    uri: ArchiveLocation,
+    /// Re-read the `path` directory instead of downloading
+    #[serde(default)]
+    from_path: bool,
+    /// Maximum archive size in bytes
+    #[serde(default = "RipeGeoUpdater::default_max_size")]
+    max_size: u64,
+    /// Total download timeout in seconds
+    #[serde(default = "RipeGeoUpdater::default_timeout")]
+    timeout: NonZeroDuration,
+    #[serde(flatten)]
+    verification: RipeGeoVerificationConfig,
+    #[serde(flatten)]
+    sanity: RipeGeoSanityChecks,
 }
 
 impl From<RipeGeoUpdaterConfig> for RipeGeoUpdater {
     fn from(config: RipeGeoUpdaterConfig) -> Self {
         Self {
             interval: config.interval.into(),
-            uri: config.uri,
+            options: RipeGeoDownloadOptions {
+                limits: RipeGeoDownloadLimits {
+                    max_size: config.max_size,
+                    timeout: config.timeout.into(),
+                },
+                verification: config.verification.into_verification(&config.uri),
+                sanity: config.sanity,
+                cache_dir: None,
+            },
+            location: config.uri,
+            from_path: config.from_path,
+            validators: ArchiveValidators::default(),
+            immediate_update: false,
             handle: None,
         }
     }
 }
 
 impl RipeGeoUpdater {
-    pub fn new(interval: impl Into<Duration>, uri: Uri) -> Self {
+    pub fn new(interval: impl Into<Duration>, location: ArchiveLocation) -> Self {
         Self {
             interval: interval.into(),
-            uri,
+            location,
+            from_path: false,
+            options: RipeGeoDownloadOptions::default(),
+            validators: ArchiveValidators::default(),
+            immediate_update: false,
             handle: None,
         }
     }
 
+    /// Read archive or directory from the location instead of the configured one
+    pub fn with_location(mut self, location: ArchiveLocation) -> Self {
+        self.location = location;
+        self
+    }
+
+    /// Store every downloaded archive in the directory
+    pub fn with_cache_dir(mut self, cache_dir: Option<PathBuf>) -> Self {
+        self.options.cache_dir = cache_dir;
+        self
+    }
+
+    /// Validators of the archive the initial database is loaded from
+    pub fn with_validators(mut self, validators: ArchiveValidators) -> Self {
+        self.validators = validators;
+        self
+    }
+
+    pub fn with_immediate_update(mut self, immediate_update: bool) -> Self {
+        self.immediate_update = immediate_update;
+        self
+    }
+
     pub fn start(&mut self, ripe_geo: &RipeGeo) -> Option<&tokio::task::JoinHandle<()>> {
         if self.handle.is_some() {
             return None;
@@ -112,22 +292,49 @@ impl RipeGeoUpdater {
         let client = Client::builder().build::<_, Body>(https);
         let overlaps_strategy = ripe_geo.overlaps_strategy;
         let ripe_geo_impl_lock = ripe_geo.inner.clone();
-        let uri = self.uri.clone();
+        let location = self.location.clone();
         let interval = self.interval;
+        let options = self.options.clone();
+        let mut validators = self.validators.clone();
+        let mut skip_sleep = self.immediate_update;
 
         self.handle = tokio::spawn(async move {
             loop {
-                tokio::time::sleep(interval).await;
-                let new_ripe_geo_impl =
-                    match RipeGeoImpl::download(&client, &uri, overlaps_strategy).await {
-                        Ok(val) => val,
-                        Err(err) => {
-                            log::warn!(
-                                r#"Error while attempting to update ripe-geo from "{uri}": {err}"#,
-                            );
-                            continue;
-                        }
-                    };
+                if !std::mem::take(&mut skip_sleep) {
+                    tokio::time::sleep(interval).await;
+                }
+                let current_stats = ripe_geo_impl_lock.read().unwrap().stats().clone();
+                let new_ripe_geo_impl = match RipeGeoImpl::download(
+                    &client,
+                    &location,
+                    overlaps_strategy,
+                    &options,
+                    &mut validators,
+                    Some(&current_stats),
+                )
+                .await
+                {
+                    Ok(Some(val)) => val,
+                    Ok(None) => {
+                        log::debug!(r#"ripe-geo archive at "{location}" is not modified"#);
+                        continue;
+                    }
+                    Err(RipeGeoDataError::DownloadError(
+                        err @ (RipeGeoDownloadError::Verification(_)
+                        | RipeGeoDownloadError::Sanity(_)),
+                    )) => {
+                        log::error!(
+                            r#"ripe-geo archive from "{location}" is rejected, keeping the current database: {err}"#,
+                        );
+                        continue;
+                    }
+                    Err(err) => {
+                        log::warn!(
+                            r#"Error while attempting to update ripe-geo from "{location}": {err}"#,
+                        );
+                        continue;
+                    }
+                };
                 {
                     let mut ripe_geo_impl = ripe_geo_impl_lock.write().unwrap();
                     let _ = std::mem::replace(
@@ -135,7 +342,7 @@ impl RipeGeoUpdater {
                         new_ripe_geo_impl,
                     );
                 }
-                log::info!(r#"ripe-geo database updated from "{uri}""#);
+                log::info!(r#"ripe-geo database updated from "{location}""#);
             }
         })
         .into();
@@ -145,83 +352,337 @@ impl RipeGeoUpdater {
 
 impl RipeGeo {
     pub fn set_updater(&mut self, updater: Option<RipeGeoUpdater>) {
-        self.updater = updater.map(RwLock::new);
+        self.updater = updater.map(|updater| Box::new(RwLock::new(updater)));
     }
 }
 
 impl RipeGeoImpl {
-    async fn download_archive<C>(
+    /// Download archive or read local archive or directory, `None` is returned if it is not
+    /// modified since the download `validators` are taken from
+    pub(super) async fn download_archive<C>(
         client: &Client<C>,
-        mut uri: Uri,
-    ) -> Result<Bytes, RipeGeoDownloadError>
+        location: &ArchiveLocation,
+        validators: &ArchiveValidators,
+        limits: RipeGeoDownloadLimits,
+    ) -> Result<Option<(ArchiveContent, ArchiveValidators)>, RipeGeoDownloadError>
     where
         C: Connect + Clone + Send + Sync + 'static,
     {
-        const MAX_ATTEMPTS: usize = 8;
-        let mut attempt = 0;
-        let response = loop {
-            let request = hyper::Request::builder().uri(&uri).body(Body::empty())?;
-            let response = client.request(request).await?;
-
-            if response.status().is_success() {
-                break response;
-            } else if response.status().is_redirection() {
-                uri = response
-                    .headers()
-                    .get("Location")
-                    .ok_or_else(|| response.status())?
-                    .as_bytes()
-                    .try_into()
-                    .map_err(|_| response.status())?;
-            } else {
-                return Err(response.status().into());
-            }
-
-            attempt += 1;
-            if attempt == MAX_ATTEMPTS {
-                return Err(response.status().into());
+        let future = async {
+            match location {
+                ArchiveLocation::Web(uri) => Ok(Self::download_archive_no_timeout(
+                    client,
+                    uri.clone(),
+                    validators,
+                    limits.max_size,
+                )
+                .await?
+                .map(|(body, validators)| (ArchiveContent::Archive(body), validators))),
+                ArchiveLocation::Local(path) => {
+                    Self::read_local_archive(path.clone(), validators, limits.max_size).await
+                }
             }
         };
-        let body = response.into_body();
-        Ok(hyper::body::to_bytes(body).await?)
+        tokio::time::timeout(limits.timeout, future)
+            .await
+            .map_err(|_| RipeGeoDownloadError::Timeout(limits.timeout))?
+    }
+
+    async fn read_local_archive(
+        path: PathBuf,
+        validators: &ArchiveValidators,
+        max_size: u64,
+    ) -> Result<Option<(ArchiveContent, ArchiveValidators)>, RipeGeoDownloadError> {
+        let current = validators.local;
+        let result =
+            tokio::task::spawn_blocking(move || read_local(&path, current, max_size)).await??;
+        Ok(result.map(|(content, stamp)| {
+            let validators = ArchiveValidators {
+                local: Some(stamp),
+                ..Default::default()
+            };
+            (content, validators)
+        }))
     }
 
+    async fn download_archive_no_timeout<C>(
+        client: &Client<C>,
+        uri: Uri,
+        validators: &ArchiveValidators,
+        max_size: u64,
+    ) -> Result<Option<(Bytes, ArchiveValidators)>, RipeGeoDownloadError>
+    where
+        C: Connect + Clone + Send + Sync + 'static,
+    {
+        let result = fetch(client, &uri, None, &validators.http, max_size).await?;
+        Ok(result.map(|(archive, http)| {
+            let validators = ArchiveValidators { http, local: None };
+            (archive, validators)
+        }))
+    }
+
+    /// Download, verify, parse and sanity check archive or local directory, and store the archive
+    /// in the cache directory if specified and the archive is valid. `None` is returned if the archive is not
+    /// modified since `validators` were obtained, otherwise they are updated. `current` is stats
+    /// of the database to be replaced
     pub async fn download<C>(
         client: &Client<C>,
-        uri: &Uri,
+        location: &ArchiveLocation,
         overlaps_strategy: RipeGeoOverlapsStrategy,
-    ) -> Result<Self, RipeGeoDataError>
+        options: &RipeGeoDownloadOptions,
+        validators: &mut ArchiveValidators,
+        current: Option<&RipeGeoStats>,
+    ) -> Result<Option<Self>, RipeGeoDataError>
     where
         C: Connect + Clone + Send + Sync + 'static,
     {
-        let body = Self::download_archive(client, uri.clone()).await?;
-        let gz_reader = flate2::bufread::GzDecoder::new(body.as_ref());
-        let mut tar_archive = tar::Archive::new(gz_reader);
-        let it = tar_archive
-            .entries()
-            .map_err(RipeGeoDataError::ArchiveReadError)?
-            .filter_map(|entry| {
-                let mut entry = entry.ok()?;
-                let path = entry.path().ok()?;
-                let path = path.into_owned();
-                let mut vec = Vec::with_capacity(entry.size() as usize);
-                if let Err(error) = entry.read_to_end(&mut vec) {
-                    return Some(Err(RipeGeoDataError::ArchiveEntryIoError { path, error }));
-                }
-                let boxed_entry: Box<dyn Read> = Box::new(Cursor::new(vec));
-                Some(Ok((path, boxed_entry)))
-            });
-        Self::from_text_files(it, overlaps_strategy)
+        let limits = options.limits;
+        let (content, new_validators) =
+            match Self::download_archive(client, location, validators, limits).await? {
+                Some(value) => value,
+                None => return Ok(None),
+            };
+        let body = match content {
+            ArchiveContent::Archive(body) => body,
+            ArchiveContent::Directory(path) => {
+                options
+                    .verification
+                    .verify_directory(&path)
+                    .map_err(RipeGeoDownloadError::from)?;
+                let ripe_geo_impl = Self::from_folder(&path, overlaps_strategy)?;
+                options
+                    .sanity
+                    .check(&ripe_geo_impl, current)
+                    .map_err(RipeGeoDownloadError::from)?;
+                *validators = new_validators;
+                return Ok(Some(ripe_geo_impl));
+            }
+        };
+        options
+            .verification
+            .verify(client, &body, limits)
+            .await
+            .map_err(RipeGeoDownloadError::from)?;
+        let ripe_geo_impl =
+            Self::from_archive(&body, overlaps_strategy, limits.max_unpacked_size())?;
+        options
+            .sanity
+            .check(&ripe_geo_impl, current)
+            .map_err(RipeGeoDownloadError::from)?;
+        if let Some(cache_dir) = &options.cache_dir {
+            let cache_dir = cache_dir.to_owned();
+            let result =
+                tokio::task::spawn_blocking(move || Self::write_cache(&cache_dir, &body)).await;
+            match result {
+                Ok(Ok(())) => {}
+                Ok(Err(err)) => log::warn!("Cannot write ripe-geo cache: {err}"),
+                Err(err) => log::warn!("Cannot write ripe-geo cache: {err}"),
+            }
+        }
+        *validators = new_validators;
+        Ok(Some(ripe_geo_impl))
+    }
+
+    fn write_cache(cache_dir: &Path, archive: &[u8]) -> std::io::Result<()> {
+        std::fs::create_dir_all(cache_dir)?;
+        write_atomically(&cache_dir.join(RIPE_GEO_CACHE_FILE), archive)
     }
 
-    pub fn from_uri(
-        uri: &Uri,
+    /// Load archive previously stored by [RipeGeoImpl::download]
+    pub fn from_cache(
+        cache_dir: &Path,
         overlaps_strategy: RipeGeoOverlapsStrategy,
-        handle: Option<Handle>,
+        max_unpacked_size: u64,
     ) -> Result<Self, RipeGeoDataError> {
+        let path = cache_dir.join(RIPE_GEO_CACHE_FILE);
+        let archive =
+            std::fs::read(&path).map_err(|error| RipeGeoDataError::FileIoError { path, error })?;
+        Self::from_archive(&archive, overlaps_strategy, max_unpacked_size)
+    }
+
+    pub fn from_location(
+        location: &ArchiveLocation,
+        overlaps_strategy: RipeGeoOverlapsStrategy,
+        options: &RipeGeoDownloadOptions,
+        handle: Option<Handle>,
+    ) -> Result<(Self, ArchiveValidators), RipeGeoDataError> {
         let https = HttpsConnector::new();
         let client = Client::builder().build::<_, Body>(https);
         let handle = handle.unwrap_or_else(Handle::current);
-        handle.block_on(Self::download(&client, uri, overlaps_strategy))
+        let mut validators = ArchiveValidators::default();
+        let ripe_geo_impl = handle
+            .block_on(Self::download(
+                &client,
+                location,
+                overlaps_strategy,
+                options,
+                &mut validators,
+                None,
+            ))?
+            // Unconditional request cannot get 304
+            .ok_or(RipeGeoDownloadError::NonSuccess(StatusCode::NOT_MODIFIED))?;
+        Ok((ripe_geo_impl, validators))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::geo::ripe_geo::archive::tests::{tar, tar_gz as archive};
+
+    async fn serve(archive: Vec<u8>) -> ArchiveLocation {
+        ArchiveLocation::Web(crate::download::tests::serve(archive, "continents.tar.gz").await)
+    }
+
+    #[tokio::test]
+    async fn conditional_download() {
+        let uri = serve(archive()).await;
+        let client = Client::new();
+        let mut validators = ArchiveValidators::default();
+        let options = RipeGeoDownloadOptions::default();
+        let overlaps = RipeGeoOverlapsStrategy::Skip;
+        let ripe_geo_impl =
+            RipeGeoImpl::download(&client, &uri, overlaps, &options, &mut validators, None).await;
+        assert!(ripe_geo_impl.unwrap().is_some());
+        assert_eq!(validators.http.etag.as_ref().unwrap(), "\"v1\"");
+        let ripe_geo_impl =
+            RipeGeoImpl::download(&client, &uri, overlaps, &options, &mut validators, None).await;
+        assert!(ripe_geo_impl.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn failed_verification() {
+        let uri = serve(archive()).await;
+        // Checksum URL serves the archive itself, which is not a checksum file
+        let verification =
+            toml::from_str::<RipeGeoVerificationConfig>(&format!("sha256_url = \"{uri}\""))
+                .unwrap()
+                .into_verification(&uri);
+        let options = RipeGeoDownloadOptions {
+            verification,
+            ..Default::default()
+        };
+        let mut validators = ArchiveValidators::default();
+        let result = RipeGeoImpl::download(
+            &Client::new(),
+            &uri,
+            RipeGeoOverlapsStrategy::Skip,
+            &options,
+            &mut validators,
+            None,
+        )
+        .await;
+        assert!(matches!(
+            result,
+            Err(RipeGeoDataError::DownloadError(
+                RipeGeoDownloadError::Verification(RipeGeoVerificationError::ChecksumFormat(_))
+            ))
+        ));
+        assert_eq!(validators, ArchiveValidators::default());
+    }
+
+    #[tokio::test]
+    async fn max_size() {
+        let archive = archive();
+        let size = archive.len() as u64;
+        let uri = serve(archive).await;
+        let limits = RipeGeoDownloadLimits {
+            max_size: size - 1,
+            timeout: Duration::from_secs(10),
+        };
+        let result =
+            RipeGeoImpl::download_archive(&Client::new(), &uri, &Default::default(), limits).await;
+        assert!(matches!(result, Err(RipeGeoDownloadError::TooLarge(max)) if max == size - 1));
+    }
+
+    #[tokio::test]
+    async fn local_directory() {
+        let dir = std::env::temp_dir().join(format!("geo302-ripe-geo-dir-{}", std::process::id()));
+        tar::Archive::new(tar().as_slice()).unpack(&dir).unwrap();
+        let location = ArchiveLocation::Local(dir.join("continents"));
+        let client = Client::new();
+        let mut validators = ArchiveValidators::default();
+        let options = RipeGeoDownloadOptions::default();
+        let overlaps = RipeGeoOverlapsStrategy::Skip;
+
+        let ripe_geo_impl = RipeGeoImpl::download(
+            &client,
+            &location,
+            overlaps,
+            &options,
+            &mut validators,
+            None,
+        )
+        .await
+        .unwrap()
+        .unwrap();
+        assert_eq!(
+            ripe_geo_impl
+                .try_lookup_continent("2.3.4.5".parse().unwrap())
+                .unwrap(),
+            Continent::Europe
+        );
+        let not_modified = RipeGeoImpl::download(
+            &client,
+            &location,
+            overlaps,
+            &options,
+            &mut validators,
+            None,
+        )
+        .await
+        .unwrap();
+        assert!(not_modified.is_none());
+
+        // Size changes too, so the update is noticed even within mtime resolution
+        std::fs::write(dir.join("continents/europe.ipv4.list"), "62.0.0.0/8\n").unwrap();
+        std::fs::write(
+            dir.join("continents/asia.ipv4.list"),
+            "1.0.0.0/8\n2.0.0.0/8\n",
+        )
+        .unwrap();
+        let ripe_geo_impl = RipeGeoImpl::download(
+            &client,
+            &location,
+            overlaps,
+            &options,
+            &mut validators,
+            None,
+        )
+        .await
+        .unwrap()
+        .unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(
+            ripe_geo_impl
+                .try_lookup_continent("2.3.4.5".parse().unwrap())
+                .unwrap(),
+            Continent::Asia
+        );
+    }
+
+    #[test]
+    fn cache() {
+        let cache_dir =
+            std::env::temp_dir().join(format!("geo302-ripe-geo-cache-{}", std::process::id()));
+        assert!(
+            RipeGeoImpl::from_cache(&cache_dir, RipeGeoOverlapsStrategy::Skip, u64::MAX).is_err()
+        );
+
+        RipeGeoImpl::write_cache(&cache_dir, &archive()).unwrap();
+        let ripe_geo_impl =
+            RipeGeoImpl::from_cache(&cache_dir, RipeGeoOverlapsStrategy::Skip, u64::MAX).unwrap();
+        assert!(!cache_dir
+            .join(format!(".{RIPE_GEO_CACHE_FILE}.tmp"))
+            .exists());
+        std::fs::remove_dir_all(&cache_dir).unwrap();
+
+        assert_eq!(
+            ripe_geo_impl
+                .try_lookup_continent("2.3.4.5".parse().unwrap())
+                .unwrap(),
+            Continent::Europe
+        );
     }
 }
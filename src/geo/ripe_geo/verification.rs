@@ -0,0 +1,246 @@
+use super::location::{ArchiveContent, ArchiveLocation};
+use super::updater::{RipeGeoDownloadError, RipeGeoDownloadLimits};
+use super::*;
+
+use hyper::client::connect::Connect;
+use hyper::client::Client;
+use minisign_verify::{PublicKey, Signature};
+use sha2::{Digest, Sha256};
+
+/// Checksum and signature files are small, limit their size independently of the archive
+const MAX_VERIFICATION_FILE_SIZE: u64 = 64 << 10;
+
+#[derive(Debug, Error)]
+pub enum RipeGeoVerificationError {
+    #[error(r#"Cannot download "{uri}": {error}"#)]
+    Download {
+        uri: ArchiveLocation,
+        error: Box<RipeGeoDownloadError>,
+    },
+    #[error(r#"Checksum file "{0}" has no SHA-256 hex digest"#)]
+    ChecksumFormat(ArchiveLocation),
+    #[error(r#"Directory "{0}" cannot be verified, use an archive instead"#)]
+    Directory(PathBuf),
+    #[error("SHA-256 checksum mismatch: expected {expected}, got {actual}")]
+    ChecksumMismatch { expected: String, actual: String },
+    #[error("Minisign signature is not valid: {0}")]
+    Signature(minisign_verify::Error),
+}
+
+#[derive(Debug, Clone)]
+struct MinisignVerification {
+    public_key: PublicKey,
+    signature_uri: ArchiveLocation,
+}
+
+/// Checks of downloaded archive performed before it replaces the current database
+#[derive(Debug, Clone, Default)]
+pub struct RipeGeoVerification {
+    sha256_uri: Option<ArchiveLocation>,
+    minisign: Option<MinisignVerification>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(try_from = "String")]
+pub struct MinisignPublicKey(PublicKey);
+
+impl TryFrom<String> for MinisignPublicKey {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        PublicKey::from_base64(s.trim())
+            .map(Self)
+            .map_err(|e| format!("invalid minisign public key: {e}"))
+    }
+}
+
+/// Verification options of `[geoip.autoupdate]` section
+#[derive(Deserialize, Debug, Default)]
+pub struct RipeGeoVerificationConfig {
+    /// URL of a file with SHA-256 hex digest of the archive, as produced by sha256sum
+    #[serde(default, alias = "sha256_uri")]
+    sha256_url: Option<ArchiveLocation>,
+    /// Base64 minisign public key, the archive must be signed with the corresponding secret key
+    #[serde(default)]
+    minisign_public_key: Option<MinisignPublicKey>,
+    /// URL of minisign signature, archive URL with ".minisig" suffix by default
+    #[serde(default, alias = "signature_uri")]
+    signature_url: Option<ArchiveLocation>,
+}
+
+impl RipeGeoVerificationConfig {
+    pub fn into_verification(self, archive_uri: &ArchiveLocation) -> RipeGeoVerification {
+        let minisign = self.minisign_public_key.map(|public_key| {
+            let signature_uri = self
+                .signature_url
+                .unwrap_or_else(|| archive_uri.with_suffix(".minisig"));
+            MinisignVerification {
+                public_key: public_key.0,
+                signature_uri,
+            }
+        });
+        RipeGeoVerification {
+            sha256_uri: self.sha256_url,
+            minisign,
+        }
+    }
+}
+
+impl RipeGeoVerification {
+    async fn download_text<C>(
+        client: &Client<C>,
+        uri: &ArchiveLocation,
+        limits: RipeGeoDownloadLimits,
+    ) -> Result<String, RipeGeoVerificationError>
+    where
+        C: Connect + Clone + Send + Sync + 'static,
+    {
+        let limits = limits.with_max_size(MAX_VERIFICATION_FILE_SIZE);
+        let download_error = |error| RipeGeoVerificationError::Download {
+            uri: uri.clone(),
+            error: Box::new(error),
+        };
+        let (content, _validators) =
+            RipeGeoImpl::download_archive(client, uri, &Default::default(), limits)
+                .await
+                .map_err(download_error)?
+                // Unconditional request cannot get 304
+                .ok_or_else(|| {
+                    download_error(RipeGeoDownloadError::NonSuccess(
+                        hyper::StatusCode::NOT_MODIFIED,
+                    ))
+                })?;
+        let bytes = match content {
+            ArchiveContent::Archive(bytes) => bytes,
+            ArchiveContent::Directory(path) => {
+                return Err(download_error(RipeGeoDownloadError::NotAFile(path)))
+            }
+        };
+        // Both checksum and signature files are ASCII, other bytes would fail parsing anyway
+        Ok(String::from_utf8_lossy(&bytes).into_owned())
+    }
+
+    /// Directories have nothing to check checksum or signature of, so they are rejected if any
+    /// verification is configured
+    pub fn verify_directory(&self, path: &Path) -> Result<(), RipeGeoVerificationError> {
+        if self.sha256_uri.is_some() || self.minisign.is_some() {
+            Err(RipeGeoVerificationError::Directory(path.to_owned()))
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Download checksum and signature files and check the archive against them
+    pub async fn verify<C>(
+        &self,
+        client: &Client<C>,
+        archive: &[u8],
+        limits: RipeGeoDownloadLimits,
+    ) -> Result<(), RipeGeoVerificationError>
+    where
+        C: Connect + Clone + Send + Sync + 'static,
+    {
+        if let Some(sha256_uri) = &self.sha256_uri {
+            let checksum_file = Self::download_text(client, sha256_uri, limits).await?;
+            let expected = parse_sha256_file(&checksum_file)
+                .ok_or_else(|| RipeGeoVerificationError::ChecksumFormat(sha256_uri.clone()))?;
+            verify_sha256(archive, expected)?;
+        }
+        if let Some(minisign) = &self.minisign {
+            let signature = Self::download_text(client, &minisign.signature_uri, limits).await?;
+            verify_minisign(archive, &minisign.public_key, &signature)?;
+        }
+        Ok(())
+    }
+}
+
+/// First word of the file if it is a SHA-256 hex digest, "sha256sum" output format is supported
+fn parse_sha256_file(s: &str) -> Option<&str> {
+    let digest = s.split_whitespace().next()?;
+    (digest.len() == 64 && digest.bytes().all(|b| b.is_ascii_hexdigit())).then_some(digest)
+}
+
+fn verify_sha256(archive: &[u8], expected: &str) -> Result<(), RipeGeoVerificationError> {
+    let actual = Sha256::digest(archive)
+        .iter()
+        .map(|byte| format!("{byte:02x}"))
+        .collect::<String>();
+    if actual.eq_ignore_ascii_case(expected) {
+        Ok(())
+    } else {
+        Err(RipeGeoVerificationError::ChecksumMismatch {
+            expected: expected.to_owned(),
+            actual,
+        })
+    }
+}
+
+fn verify_minisign(
+    archive: &[u8],
+    public_key: &PublicKey,
+    signature: &str,
+) -> Result<(), RipeGeoVerificationError> {
+    let signature = Signature::decode(signature).map_err(RipeGeoVerificationError::Signature)?;
+    // Legacy signatures of non-prehashed files are not accepted
+    public_key
+        .verify(archive, &signature, false)
+        .map_err(RipeGeoVerificationError::Signature)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sha256() {
+        const TEST_SHA256: &str =
+            "9f86d081884c7d659a2feaa0c55ad015a3bf4f1b2b0b822cd15d6c15b0f00a08";
+        let checksum_file = format!("{TEST_SHA256}  continents.tar.gz\n");
+        let expected = parse_sha256_file(&checksum_file).unwrap();
+        assert!(verify_sha256(b"test", expected).is_ok());
+        assert!(matches!(
+            verify_sha256(b"Test", expected),
+            Err(RipeGeoVerificationError::ChecksumMismatch { .. })
+        ));
+        assert_eq!(parse_sha256_file("not a checksum"), None);
+        assert_eq!(parse_sha256_file(""), None);
+    }
+
+    #[test]
+    fn minisign() {
+        // Test vector of minisign-verify crate
+        let public_key =
+            PublicKey::from_base64("RWQf6LRCGA9i53mlYecO4IzT51TGPpvWucNSCh1CBM0QTaLn73Y7GFO3")
+                .unwrap();
+        let signature = "untrusted comment: signature from minisign secret key
+RUQf6LRCGA9i559r3g7V1qNyJDApGip8MfqcadIgT9CuhV3EMhHoN1mGTkUidF/z7SrlQgXdy8ofjb7bNJJylDOocrCo8KLzZwo=
+trusted comment: timestamp:1556193335\tfile:test
+y/rUw2y8/hOUYjZU71eHp/Wo1KZ40fGy2VJEDl34XMJM+TX48Ss/17u3IvIfbVR1FkZZSNCisQbuQY+bHwhEBg==";
+        assert!(verify_minisign(b"test", &public_key, signature).is_ok());
+        assert!(matches!(
+            verify_minisign(b"Test", &public_key, signature),
+            Err(RipeGeoVerificationError::Signature(
+                minisign_verify::Error::InvalidSignature
+            ))
+        ));
+    }
+
+    #[test]
+    fn config() {
+        let config: RipeGeoVerificationConfig = toml::from_str(
+            r#"minisign_public_key = "RWQf6LRCGA9i53mlYecO4IzT51TGPpvWucNSCh1CBM0QTaLn73Y7GFO3""#,
+        )
+        .unwrap();
+        let archive_uri =
+            ArchiveLocation::try_from("https://example.org/continents.tar.gz".to_owned()).unwrap();
+        let verification = config.into_verification(&archive_uri);
+        assert_eq!(
+            verification.minisign.unwrap().signature_uri.to_string(),
+            "https://example.org/continents.tar.gz.minisig"
+        );
+        assert!(toml::from_str::<RipeGeoVerificationConfig>(
+            r#"minisign_public_key = "not a key""#
+        )
+        .is_err());
+    }
+}
@@ -1,29 +1,134 @@
+use crate::ip_network::{any_contains, IpNetwork};
+
 use hyper::HeaderMap;
-use std::net::IpAddr;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
 
-pub fn client_ip(headers: &HeaderMap, header_names: &[String], recursive: bool) -> Option<IpAddr> {
-    header_names
-        .iter()
-        .filter_map(|name| {
-            let values = headers.get_all(name);
-            let mut it_values = values.iter();
-            if recursive {
-                it_values.next()
-            } else {
-                it_values.next_back()
+const FORWARDED: &str = "forwarded";
+
+/// Addresses from all lines of the first found header, `None` stands for elements which are not
+/// valid IP addresses, like "unknown" or obfuscated identifiers.
+///
+/// RFC 7239 "Forwarded" header is parsed for its "for" parameter, other headers are treated as
+/// comma-separated lists of addresses, like "X-Forwarded-For".
+fn header_addresses(headers: &HeaderMap, header_names: &[String]) -> Option<Vec<Option<IpAddr>>> {
+    header_names.iter().find_map(|name| {
+        let mut values = headers.get_all(name).iter().peekable();
+        values.peek()?;
+        let values = values.map(|value| value.to_str().unwrap_or_default());
+        let addresses = if name.eq_ignore_ascii_case(FORWARDED) {
+            values
+                .flat_map(|value| split_unquoted(value, ','))
+                .map(forwarded_element_for)
+                .collect()
+        } else {
+            values
+                .flat_map(|value| value.split(','))
+                .map(|element| parse_ip(element.trim()))
+                .collect()
+        };
+        Some(addresses)
+    })
+}
+
+/// Parse IP address, possibly with a port, which some proxies add
+fn parse_ip(s: &str) -> Option<IpAddr> {
+    s.parse::<IpAddr>()
+        .or_else(|_| s.parse::<SocketAddr>().map(|address| address.ip()))
+        .ok()
+}
+
+/// Split by the separator, ignoring separators inside quoted strings
+fn split_unquoted(s: &str, separator: char) -> Vec<&str> {
+    let mut parts = vec![];
+    let mut start = 0;
+    let mut in_quotes = false;
+    let mut escaped = false;
+    for (i, c) in s.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' if in_quotes => escaped = true,
+            '"' => in_quotes = !in_quotes,
+            c if c == separator && !in_quotes => {
+                parts.push(s[start..i].trim());
+                start = i + c.len_utf8();
             }
-        })
-        .next()
-        .and_then(|value| {
-            let value = value.to_str().ok()?;
-            let mut split = value.split(',');
-            if recursive {
-                split.next()
-            } else {
-                split.next_back()
+            _ => {}
+        }
+    }
+    parts.push(s[start..].trim());
+    parts
+}
+
+/// Remove quotes and unescape quoted-pairs of a quoted string, return the token as is otherwise
+fn unquote(s: &str) -> String {
+    match s.strip_prefix('"').and_then(|s| s.strip_suffix('"')) {
+        Some(inner) => {
+            let mut unquoted = String::with_capacity(inner.len());
+            let mut chars = inner.chars();
+            while let Some(c) = chars.next() {
+                match c {
+                    '\\' => unquoted.extend(chars.next()),
+                    c => unquoted.push(c),
+                }
             }
-        })
-        .and_then(|s| s.trim().parse::<IpAddr>().ok())
+            unquoted
+        }
+        None => s.to_owned(),
+    }
+}
+
+/// Address of "for" parameter of a single "Forwarded" element like `for=192.0.2.60;proto=http`
+fn forwarded_element_for(element: &str) -> Option<IpAddr> {
+    let value = split_unquoted(element, ';').into_iter().find_map(|pair| {
+        let (name, value) = pair.split_once('=')?;
+        name.trim()
+            .eq_ignore_ascii_case("for")
+            .then(|| unquote(value.trim()))
+    })?;
+    parse_forwarded_node(&value)
+}
+
+/// Parse node of RFC 7239: `IPv4[:port]`, `"[IPv6][:port]"`, `unknown` or `_obfuscated`
+fn parse_forwarded_node(node: &str) -> Option<IpAddr> {
+    if let Some(rest) = node.strip_prefix('[') {
+        let (ip, _port) = rest.split_once(']')?;
+        return ip.parse::<Ipv6Addr>().ok().map(IpAddr::V6);
+    }
+    let ip = match node.split_once(':') {
+        // Port could be obfuscated, so we don't parse it
+        Some((ip, _port)) => ip,
+        None => node,
+    };
+    ip.parse::<Ipv4Addr>().ok().map(IpAddr::V4)
+}
+
+pub fn client_ip(headers: &HeaderMap, header_names: &[String], recursive: bool) -> Option<IpAddr> {
+    let addresses = header_addresses(headers, header_names)?;
+    if recursive {
+        addresses.into_iter().next()
+    } else {
+        addresses.into_iter().next_back()
+    }?
+}
+
+/// Walk the header from the right skipping trusted proxies and return the first untrusted
+/// address. If all the addresses are trusted, the left-most one is returned. Returns `None` if
+/// some address on the way cannot be parsed.
+pub fn client_ip_trusted(
+    headers: &HeaderMap,
+    header_names: &[String],
+    trusted_proxies: &[IpNetwork],
+) -> Option<IpAddr> {
+    let addresses = header_addresses(headers, header_names)?;
+    let mut client_ip = None;
+    for ip in addresses.into_iter().rev() {
+        let ip = ip?;
+        client_ip = Some(ip);
+        if !any_contains(trusted_proxies, ip) {
+            break;
+        }
+    }
+    client_ip
 }
 
 #[cfg(test)]
@@ -129,4 +234,166 @@ mod tests {
             assert_eq!(actual, Some(ip_expected));
         }
     }
+
+    fn xff_headers(value: &str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert("X-FORWARDED-FOR", value.parse().unwrap());
+        headers
+    }
+
+    #[test]
+    fn client_ip_with_port() {
+        let headers = xff_headers("192.0.2.1:4711, [2001:db8::1]:80");
+        let header_names = ["X-FORWARDED-FOR".to_string()];
+        assert_eq!(
+            client_ip(&headers, &header_names, true),
+            Some("192.0.2.1".parse().unwrap())
+        );
+        assert_eq!(
+            client_ip(&headers, &header_names, false),
+            Some("2001:db8::1".parse().unwrap())
+        );
+    }
+
+    #[test]
+    fn client_ip_multiple_header_lines() {
+        let mut headers = xff_headers("192.0.2.1, 10.0.0.1");
+        headers.append("X-FORWARDED-FOR", "10.0.0.2".parse().unwrap());
+        let header_names = ["X-FORWARDED-FOR".to_string()];
+        assert_eq!(
+            client_ip(&headers, &header_names, true),
+            Some("192.0.2.1".parse().unwrap())
+        );
+        assert_eq!(
+            client_ip(&headers, &header_names, false),
+            Some("10.0.0.2".parse().unwrap())
+        );
+    }
+
+    #[test]
+    fn client_ip_trusted_skips_trusted_hops() {
+        let trusted: Vec<IpNetwork> = vec!["10.0.0.0/8".parse().unwrap()];
+        let header_names = ["X-FORWARDED-FOR".to_string()];
+        // The client has spoofed the first address
+        let headers = xff_headers("1.1.1.1, 192.0.2.1, 10.0.0.1, 10.0.0.2");
+        assert_eq!(
+            client_ip_trusted(&headers, &header_names, &trusted),
+            Some("192.0.2.1".parse().unwrap())
+        );
+    }
+
+    #[test]
+    fn client_ip_trusted_all_trusted() {
+        let trusted: Vec<IpNetwork> = vec!["10.0.0.0/8".parse().unwrap()];
+        let header_names = ["X-FORWARDED-FOR".to_string()];
+        let headers = xff_headers("10.1.0.1, 10.0.0.1");
+        assert_eq!(
+            client_ip_trusted(&headers, &header_names, &trusted),
+            Some("10.1.0.1".parse().unwrap())
+        );
+    }
+
+    #[test]
+    fn client_ip_trusted_invalid_hop() {
+        let trusted: Vec<IpNetwork> = vec!["10.0.0.0/8".parse().unwrap()];
+        let header_names = ["X-FORWARDED-FOR".to_string()];
+        let headers = xff_headers("192.0.2.1, unknown, 10.0.0.1");
+        assert_eq!(client_ip_trusted(&headers, &header_names, &trusted), None);
+    }
+
+    #[test]
+    fn client_ip_trusted_no_header() {
+        let trusted: Vec<IpNetwork> = vec!["10.0.0.0/8".parse().unwrap()];
+        let header_names = ["X-FORWARDED-FOR".to_string()];
+        assert_eq!(
+            client_ip_trusted(&HeaderMap::new(), &header_names, &trusted),
+            None
+        );
+    }
+
+    fn forwarded_headers(value: &str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert("FORWARDED", value.parse().unwrap());
+        headers
+    }
+
+    #[test]
+    fn forwarded_element() {
+        for (element, expected) in [
+            (
+                "for=192.0.2.60;proto=http;by=203.0.113.43",
+                Some("192.0.2.60"),
+            ),
+            ("For=\"192.0.2.60:4711\"", Some("192.0.2.60")),
+            (
+                "for=\"[2001:db8:cafe::17]:4711\"",
+                Some("2001:db8:cafe::17"),
+            ),
+            ("for=\"[2001:db8:cafe::17]\"", Some("2001:db8:cafe::17")),
+            (
+                "proto=https; for=\"[2001:db8::1]:_hidden\"",
+                Some("2001:db8::1"),
+            ),
+            ("for=192.0.2.43:_port", Some("192.0.2.43")),
+            ("for=unknown", None),
+            ("for=_hidden", None),
+            ("for=\"_gazonk\"", None),
+            ("proto=https;by=203.0.113.43", None),
+            ("for=2001:db8::1", None),
+        ] {
+            assert_eq!(
+                forwarded_element_for(element),
+                expected.map(|ip| ip.parse().unwrap()),
+                "{element}"
+            );
+        }
+    }
+
+    #[test]
+    fn split_unquoted_respects_quotes() {
+        assert_eq!(
+            split_unquoted(r#"for="a,b";by=c, for=d"#, ','),
+            vec![r#"for="a,b";by=c"#, "for=d"]
+        );
+        assert_eq!(
+            split_unquoted(r#"for="a\",b";by=c"#, ';'),
+            vec![r#"for="a\",b""#, "by=c"]
+        );
+        assert_eq!(unquote(r#""a\"b""#), r#"a"b"#);
+    }
+
+    #[test]
+    fn client_ip_forwarded() {
+        let headers = forwarded_headers(
+            "for=192.0.2.43;proto=https, for=\"[2001:db8:cafe::17]:4711\";proto=https",
+        );
+        let header_names = ["Forwarded".to_string()];
+        assert_eq!(
+            client_ip(&headers, &header_names, true),
+            Some("192.0.2.43".parse().unwrap())
+        );
+        assert_eq!(
+            client_ip(&headers, &header_names, false),
+            Some("2001:db8:cafe::17".parse().unwrap())
+        );
+    }
+
+    #[test]
+    fn client_ip_forwarded_obfuscated() {
+        let headers = forwarded_headers("for=_hidden, for=192.0.2.43");
+        let header_names = ["Forwarded".to_string()];
+        assert_eq!(client_ip(&headers, &header_names, true), None);
+    }
+
+    #[test]
+    fn client_ip_trusted_forwarded() {
+        let trusted: Vec<IpNetwork> = vec!["10.0.0.0/8".parse().unwrap()];
+        let mut headers = forwarded_headers("for=1.1.1.1, for=192.0.2.43;proto=https");
+        headers.append("FORWARDED", "for=10.0.0.1;by=10.0.0.2".parse().unwrap());
+        let header_names = ["forwarded".to_string(), "x-forwarded-for".to_string()];
+        assert_eq!(
+            client_ip_trusted(&headers, &header_names, &trusted),
+            Some("192.0.2.43".parse().unwrap())
+        );
+    }
 }
@@ -7,8 +7,8 @@ use hyper::StatusCode;
 use hyper_tls::HttpsConnector;
 use serde::Deserialize;
 
-use std::sync::atomic;
-use std::time::Duration;
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
 use thiserror::Error;
 
 #[derive(Debug, Error)]
@@ -45,43 +45,42 @@ impl HealthCheckConfig {
         Ok(response.status())
     }
 
+    /// Start a checking task per unique health-check URI, so mirrors sharing the URI are probed
+    /// once
     pub fn start(self, mirrors: &[Mirror]) -> HealthCheck {
         let https = HttpsConnector::new();
         let http_client = Client::builder().build::<_, hyper::Body>(https);
-        let handles = mirrors
-            .iter()
-            .map(|mirror| {
+        let mut mirrors_by_uri: HashMap<&Uri, Vec<Mirror>> = HashMap::new();
+        for mirror in mirrors {
+            mirrors_by_uri
+                .entry(&mirror.healthcheck)
+                .or_default()
+                .push(mirror.clone());
+        }
+        let handles = mirrors_by_uri
+            .into_iter()
+            .map(|(uri, mirrors)| {
                 let http_client = http_client.clone();
-                let mirror = mirror.clone();
+                let uri = uri.clone();
                 let HealthCheckConfig { interval, timeout } = self.clone();
                 let interval = interval.into();
                 let timeout = timeout.into();
                 tokio::spawn(async move {
                     loop {
-                        let status =
-                            Self::get_status(&http_client, mirror.healthcheck.clone(), timeout)
-                                .await;
-                        // Use Result.is_ok_and when stabilizes
-                        // https://github.com/rust-lang/rust/issues/93050
-                        let new_available = match status {
-                            Ok(success) => success.is_success(),
-                            Err(_) => false,
+                        let start = Instant::now();
+                        let status = Self::get_status(&http_client, uri.clone(), timeout).await;
+                        let response_time = status.is_ok().then(|| start.elapsed());
+                        let error = match status {
+                            Ok(status) if status.is_success() => None,
+                            Ok(status) => Some(status.to_string()),
+                            Err(e) => Some(e.to_string()),
                         };
-                        mirror
-                            .available
-                            .store(new_available, atomic::Ordering::Release);
-                        match (new_available, status) {
-                            (true, Ok(_)) => log::info!("{} is alive", mirror.healthcheck),
-                            (false, Ok(status)) => {
-                                log::warn!("{} is unavailable: {}", mirror.healthcheck, status)
-                            }
-                            (_, Err(e)) => {
-                                log::warn!(
-                                    "{} is unavailable: {}",
-                                    mirror.healthcheck,
-                                    e.to_string()
-                                )
-                            }
+                        match &error {
+                            None => log::info!("{} is alive", uri),
+                            Some(e) => log::warn!("{} is unavailable: {}", uri, e),
+                        }
+                        for mirror in &mirrors {
+                            mirror.update_health(response_time, error.clone());
                         }
                         tokio::time::sleep(interval).await;
                     }
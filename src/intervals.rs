@@ -152,6 +152,61 @@ where
         }
     }
 
+    /// Build from non-empty and non-overlapping intervals in ascending order of keys, `None` is
+    /// returned otherwise, including the case of interval end overflow
+    pub fn from_sorted(keys: Vec<K>, sizes_values: Vec<(S, V)>) -> Option<Self>
+    where
+        K: CheckedAdd<S>,
+    {
+        if keys.len() != sizes_values.len() {
+            return None;
+        }
+        for (index, (&key, (size, _value))) in keys.iter().zip(sizes_values.iter()).enumerate() {
+            let end = key.checked_add(*size)?;
+            if end <= key {
+                return None;
+            }
+            match keys.get(index + 1) {
+                Some(&next_key) if end > next_key => return None,
+                _ => {}
+            }
+        }
+        Some(Self { keys, sizes_values })
+    }
+
+    /// Intervals in ascending order of keys
+    pub fn iter(&self) -> impl Iterator<Item = (&K, &(S, V))> {
+        self.keys.iter().zip(self.sizes_values.iter())
+    }
+
+    /// Merge touching intervals with equal values, so sizes are not powers of two anymore.
+    /// Lookups give the same values, but [IntervalVec::get_key_size] returns merged intervals
+    pub fn coalesce(&mut self)
+    where
+        S: std::ops::Add<S, Output = S>,
+        V: PartialEq,
+    {
+        let mut len = 0;
+        for index in 0..self.keys.len() {
+            if len > 0 {
+                let last = len - 1;
+                let (last_size, last_value) = &self.sizes_values[last];
+                let (size, value) = &self.sizes_values[index];
+                if self.keys[last] + *last_size == self.keys[index] && last_value == value {
+                    self.sizes_values[last].0 = *last_size + *size;
+                    continue;
+                }
+            }
+            self.keys.swap(len, index);
+            self.sizes_values.swap(len, index);
+            len += 1;
+        }
+        self.keys.truncate(len);
+        self.keys.shrink_to_fit();
+        self.sizes_values.truncate(len);
+        self.sizes_values.shrink_to_fit();
+    }
+
     pub fn len(&self) -> usize {
         self.keys.len()
     }
@@ -281,6 +336,25 @@ impl<K, V, S> From<IntervalBTreeMap<K, V, S>> for Intervals<K, V, S> {
     }
 }
 
+/// Addition which reports overflow, it is used to validate intervals from untrusted sources
+pub trait CheckedAdd<S = Self>: Sized {
+    fn checked_add(self, size: S) -> Option<Self>;
+}
+
+macro_rules! impl_checked_add {
+    ($($t: ty),*) => {
+        $(
+            impl CheckedAdd for $t {
+                fn checked_add(self, size: Self) -> Option<Self> {
+                    <$t>::checked_add(self, size)
+                }
+            }
+        )*
+    };
+}
+
+impl_checked_add!(u8, u16, u32, u64, u128, usize, i8, i16, i32, i64, i128, isize);
+
 #[derive(Debug, Error)]
 pub struct OccupiedError<K, S> {
     pub key: K,
@@ -332,6 +406,50 @@ mod tests {
         assert_eq!(interval_tree.get_key_size(100), None);
     }
 
+    #[test]
+    fn vec_from_sorted() {
+        let vecs = IntervalVec::from_sorted(vec![0, 2, 4], vec![(2, 0), (2, 1), (2, 2)]).unwrap();
+        assert_eq!(vecs.get(3), Some(&1));
+        assert_eq!(
+            vecs.iter().map(|(k, _)| *k).collect::<Vec<_>>(),
+            vec![0, 2, 4]
+        );
+        assert!(IntervalVec::from_sorted(vec![0, 0], vec![(1, 0), (1, 1)]).is_none());
+        assert!(IntervalVec::from_sorted(vec![2, 0], vec![(1, 0), (1, 1)]).is_none());
+        assert!(IntervalVec::<i32, i32>::from_sorted(vec![0], vec![]).is_none());
+        // Empty interval
+        assert!(IntervalVec::from_sorted(vec![0, 2], vec![(0, 0), (1, 1)]).is_none());
+        // Overlapping intervals
+        assert!(IntervalVec::from_sorted(vec![0, 2], vec![(3, 0), (1, 1)]).is_none());
+        // Interval end overflows
+        assert!(IntervalVec::from_sorted(vec![0_u8, 200], vec![(1, 0), (56, 1)]).is_none());
+        assert!(IntervalVec::from_sorted(vec![0_u8, 200], vec![(1, 0), (55, 1)]).is_some());
+    }
+
+    #[test]
+    fn vec_coalesce() {
+        let mut interval_tree = IntervalBTreeMap::new();
+        interval_tree.try_insert(0, 2, 0).unwrap();
+        interval_tree.try_insert(2, 2, 0).unwrap();
+        interval_tree.try_insert(4, 4, 0).unwrap();
+        interval_tree.try_insert(8, 1, 1).unwrap();
+        // Gap between 9 and 10 is kept
+        interval_tree.try_insert(10, 2, 1).unwrap();
+        interval_tree.try_insert(12, 2, 1).unwrap();
+        interval_tree.try_insert(14, 2, 0).unwrap();
+        let mut vecs: IntervalVec<_, _> = interval_tree.into();
+        vecs.coalesce();
+        assert_eq!(
+            vecs.iter().map(|(k, s_v)| (*k, *s_v)).collect::<Vec<_>>(),
+            vec![(0, (8, 0)), (8, (1, 1)), (10, (4, 1)), (14, (2, 0))]
+        );
+        assert_eq!(vecs.get(7), Some(&0));
+        assert_eq!(vecs.get(9), None);
+        assert_eq!(vecs.get(13), Some(&1));
+        assert_eq!(vecs.get_key_size(5), Some((0, 8)));
+        assert_eq!(vecs.get(16), None);
+    }
+
     #[test]
     fn overlaps() {
         let mut interval_tree = IntervalBTreeMap::new();
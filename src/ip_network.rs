@@ -0,0 +1,140 @@
+use crate::canonical_ip::CanonicalIpAddr;
+
+use serde::Deserialize;
+use std::fmt;
+use std::net::IpAddr;
+use std::str::FromStr;
+use thiserror::Error;
+
+#[derive(Error, Debug, PartialEq, Eq)]
+#[error(r#"Invalid IP network "{0}", must be "ADDRESS/PREFIX" or "ADDRESS""#)]
+pub struct InvalidIpNetwork(String);
+
+/// IP network in CIDR notation, a bare IP address is a network of a single address
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(try_from = "String")]
+pub struct IpNetwork {
+    address: IpAddr,
+    prefix: u8,
+}
+
+impl IpNetwork {
+    pub fn new(address: IpAddr, prefix: u8) -> Option<Self> {
+        let max_prefix = match address {
+            IpAddr::V4(_) => 32,
+            IpAddr::V6(_) => 128,
+        };
+        if prefix > max_prefix {
+            return None;
+        }
+        Some(Self { address, prefix })
+    }
+
+    /// Check if the network contains the address, IPv4-mapped IPv6 addresses are matched against
+    /// IPv4 networks
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        match (self.address, ip.to_canonical_ip()) {
+            (IpAddr::V4(network), IpAddr::V4(ip)) => {
+                let mask = u32::MAX.checked_shl(32 - self.prefix as u32).unwrap_or(0);
+                u32::from(network) & mask == u32::from(ip) & mask
+            }
+            (IpAddr::V6(network), IpAddr::V6(ip)) => {
+                let mask = u128::MAX.checked_shl(128 - self.prefix as u32).unwrap_or(0);
+                u128::from(network) & mask == u128::from(ip) & mask
+            }
+            _ => false,
+        }
+    }
+}
+
+/// Check if any of the networks contains the address
+pub fn any_contains(networks: &[IpNetwork], ip: IpAddr) -> bool {
+    networks.iter().any(|network| network.contains(ip))
+}
+
+impl FromStr for IpNetwork {
+    type Err = InvalidIpNetwork;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let error = || InvalidIpNetwork(s.to_owned());
+        let (address, prefix) = match s.split_once('/') {
+            Some((address, prefix)) => {
+                let address: IpAddr = address.parse().map_err(|_| error())?;
+                (address, prefix.parse().map_err(|_| error())?)
+            }
+            None => {
+                let address: IpAddr = s.parse().map_err(|_| error())?;
+                let prefix = if address.is_ipv4() { 32 } else { 128 };
+                (address, prefix)
+            }
+        };
+        Self::new(address, prefix).ok_or_else(error)
+    }
+}
+
+impl TryFrom<String> for IpNetwork {
+    type Error = InvalidIpNetwork;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+impl fmt::Display for IpNetwork {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.address, self.prefix)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_network() {
+        let network: IpNetwork = "10.0.0.0/8".parse().unwrap();
+        assert_eq!(network.to_string(), "10.0.0.0/8");
+        let network: IpNetwork = "2001:db8::/32".parse().unwrap();
+        assert_eq!(network.to_string(), "2001:db8::/32");
+        let network: IpNetwork = "192.0.2.1".parse().unwrap();
+        assert_eq!(network.to_string(), "192.0.2.1/32");
+    }
+
+    #[test]
+    fn parse_invalid_network() {
+        for s in [
+            "10.0.0.0/33",
+            "::/129",
+            "10.0.0/8",
+            "localhost",
+            "10.0.0.0/",
+        ] {
+            assert!(s.parse::<IpNetwork>().is_err(), "{s}");
+        }
+    }
+
+    #[test]
+    fn contains_ipv4() {
+        let network: IpNetwork = "10.1.0.0/16".parse().unwrap();
+        assert!(network.contains("10.1.2.3".parse().unwrap()));
+        assert!(network.contains("::ffff:10.1.2.3".parse().unwrap()));
+        assert!(!network.contains("10.2.0.1".parse().unwrap()));
+        assert!(!network.contains("2001:db8::1".parse().unwrap()));
+    }
+
+    #[test]
+    fn contains_ipv6() {
+        let network: IpNetwork = "2001:db8::/32".parse().unwrap();
+        assert!(network.contains("2001:db8:1::1".parse().unwrap()));
+        assert!(!network.contains("2001:db9::1".parse().unwrap()));
+        assert!(!network.contains("10.1.2.3".parse().unwrap()));
+    }
+
+    #[test]
+    fn contains_everything() {
+        let network: IpNetwork = "0.0.0.0/0".parse().unwrap();
+        assert!(network.contains("255.255.255.255".parse().unwrap()));
+        let network: IpNetwork = "::/0".parse().unwrap();
+        assert!(network.contains("2001:db8::1".parse().unwrap()));
+    }
+}
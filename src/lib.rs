@@ -1,17 +1,35 @@
 #[cfg(not(any(feature = "maxminddb", feature = "ripe-geo")))]
 compile_error!("At least one of geo-IP database features must be enabled");
 
+mod access_log;
 // Remove after IpAddr::to_canonical stabilizes
 // https://github.com/rust-lang/rust/issues/27709
 mod canonical_ip;
 pub mod config;
+#[cfg(any(feature = "maxminddb-autoupdate", feature = "ripe-geo-autoupdate"))]
+mod download;
+mod fallback;
 pub mod geo;
 mod header_tools;
 mod healthcheck;
 #[cfg(feature = "ripe-geo")]
 pub mod intervals;
+mod ip_network;
+mod logging;
+mod metalink;
 mod mirror;
+mod mirror_list;
 mod non_zero_duration;
+mod proxy_protocol;
+mod redirect;
+mod rewrite;
+mod routes;
+pub mod server;
 pub mod service;
+mod spillover;
+mod time_format;
+#[cfg(feature = "tls")]
+pub mod tls;
 mod unavailable;
 mod uri_tools;
+mod virtual_host;
@@ -0,0 +1,496 @@
+//! Diagnostic logger writing to stderr, a rotated file or syslog, with per-module levels
+use crate::non_zero_duration::NonZeroDuration;
+use crate::time_format::rfc3339;
+
+use log::{Level, LevelFilter, Log, Metadata, Record};
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Write};
+#[cfg(unix)]
+use std::os::unix::net::UnixDatagram;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime};
+use thiserror::Error;
+
+#[derive(Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+enum LogTarget {
+    #[default]
+    Stderr,
+    File,
+    #[cfg(unix)]
+    Syslog,
+}
+
+#[derive(Deserialize, Debug, Default)]
+#[serde(deny_unknown_fields)]
+pub struct LoggingConfig {
+    #[serde(default)]
+    target: LogTarget,
+    /// Log file path for "file" target
+    #[serde(default)]
+    path: Option<PathBuf>,
+    /// Rotate the file when it would exceed this size in bytes
+    #[serde(default)]
+    rotate_size: Option<u64>,
+    /// Rotate the file this number of seconds after it was opened
+    #[serde(default)]
+    rotate_interval: Option<NonZeroDuration>,
+    /// Number of rotated files to keep
+    #[serde(default = "LoggingConfig::default_keep")]
+    keep: usize,
+    #[serde(default = "LoggingConfig::default_syslog_socket")]
+    syslog_socket: PathBuf,
+    #[serde(default)]
+    syslog_facility: SyslogFacility,
+    /// Per-module levels overriding top-level log_level, e.g. { "geo302::healthcheck" = "warn" }
+    #[serde(default)]
+    levels: HashMap<String, LevelFilter>,
+}
+
+impl LoggingConfig {
+    fn default_keep() -> usize {
+        5
+    }
+
+    fn default_syslog_socket() -> PathBuf {
+        "/dev/log".into()
+    }
+
+    /// Install global logger, `level` is used for modules not specified in `levels`
+    pub fn init(self, level: Level) -> Result<&'static Logger, LoggingError> {
+        let output = match self.target {
+            LogTarget::Stderr => Output::Stderr,
+            LogTarget::File => {
+                let path = self.path.ok_or(LoggingError::NoPath)?;
+                Output::File(Mutex::new(RotatingFile::open(
+                    path,
+                    self.rotate_size,
+                    self.rotate_interval.map(Into::into),
+                    self.keep,
+                )?))
+            }
+            #[cfg(unix)]
+            LogTarget::Syslog => Output::Syslog(Syslog {
+                socket: UnixDatagram::unbound().map_err(LoggingError::Syslog)?,
+                path: self.syslog_socket,
+                facility: self.syslog_facility,
+                process_id: std::process::id(),
+            }),
+        };
+        let logger = Logger::new(level.to_level_filter(), self.levels, output);
+        let max_level = logger.max_level();
+        let logger: &'static Logger = Box::leak(Box::new(logger));
+        log::set_logger(logger)?;
+        log::set_max_level(max_level);
+        Ok(logger)
+    }
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+enum SyslogFacility {
+    Kern,
+    User,
+    Mail,
+    #[default]
+    Daemon,
+    Auth,
+    Syslog,
+    Lpr,
+    News,
+    Uucp,
+    Cron,
+    Authpriv,
+    Ftp,
+    Local0,
+    Local1,
+    Local2,
+    Local3,
+    Local4,
+    Local5,
+    Local6,
+    Local7,
+}
+
+impl SyslogFacility {
+    fn code(self) -> u8 {
+        match self {
+            Self::Kern => 0,
+            Self::User => 1,
+            Self::Mail => 2,
+            Self::Daemon => 3,
+            Self::Auth => 4,
+            Self::Syslog => 5,
+            Self::Lpr => 6,
+            Self::News => 7,
+            Self::Uucp => 8,
+            Self::Cron => 9,
+            Self::Authpriv => 10,
+            Self::Ftp => 11,
+            Self::Local0 => 16,
+            Self::Local1 => 17,
+            Self::Local2 => 18,
+            Self::Local3 => 19,
+            Self::Local4 => 20,
+            Self::Local5 => 21,
+            Self::Local6 => 22,
+            Self::Local7 => 23,
+        }
+    }
+}
+
+/// RFC 5424 messages sent to a local Unix datagram socket
+#[cfg(unix)]
+#[derive(Debug)]
+struct Syslog {
+    socket: UnixDatagram,
+    path: PathBuf,
+    facility: SyslogFacility,
+    process_id: u32,
+}
+
+#[cfg(unix)]
+impl Syslog {
+    fn severity(level: Level) -> u8 {
+        match level {
+            Level::Error => 3,
+            Level::Warn => 4,
+            Level::Info => 6,
+            Level::Debug | Level::Trace => 7,
+        }
+    }
+
+    fn message(&self, time: SystemTime, record: &Record) -> String {
+        // <PRI>VERSION TIMESTAMP HOSTNAME APP-NAME PROCID MSGID STRUCTURED-DATA MSG
+        // MSGID is limited to 32 printable ASCII characters, so the target goes to MSG instead
+        format!(
+            "<{}>1 {} - geo302 {} - - [{}] {}",
+            self.facility.code() * 8 + Self::severity(record.level()),
+            rfc3339(time),
+            self.process_id,
+            record.target(),
+            record.args(),
+        )
+    }
+
+    fn send(&self, record: &Record) -> io::Result<()> {
+        let message = self.message(SystemTime::now(), record);
+        self.socket.send_to(message.as_bytes(), &self.path)?;
+        Ok(())
+    }
+}
+
+#[derive(Debug)]
+struct RotatingFile {
+    path: PathBuf,
+    file: File,
+    size: u64,
+    opened_at: SystemTime,
+    rotate_size: Option<u64>,
+    rotate_interval: Option<Duration>,
+    keep: usize,
+}
+
+impl RotatingFile {
+    fn open(
+        path: PathBuf,
+        rotate_size: Option<u64>,
+        rotate_interval: Option<Duration>,
+        keep: usize,
+    ) -> Result<Self, LoggingError> {
+        let (file, size) = Self::open_file(&path).map_err(|error| LoggingError::Open {
+            path: path.clone(),
+            error,
+        })?;
+        Ok(Self {
+            path,
+            file,
+            size,
+            opened_at: SystemTime::now(),
+            rotate_size,
+            rotate_interval,
+            keep,
+        })
+    }
+
+    fn open_file(path: &Path) -> io::Result<(File, u64)> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        let size = file.metadata()?.len();
+        Ok((file, size))
+    }
+
+    /// Reopen the file by its path, e.g. after it was moved by logrotate
+    fn reopen(&mut self) -> io::Result<()> {
+        let (file, size) = Self::open_file(&self.path)?;
+        self.file = file;
+        self.size = size;
+        self.opened_at = SystemTime::now();
+        Ok(())
+    }
+
+    fn rotated_path(&self, index: usize) -> PathBuf {
+        let mut path = self.path.clone().into_os_string();
+        path.push(format!(".{index}"));
+        path.into()
+    }
+
+    /// Shift "path.N" to "path.N+1" dropping the oldest one, move "path" to "path.1" and reopen
+    fn rotate(&mut self) -> io::Result<()> {
+        let ignore_not_found = |result: io::Result<()>| match result {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+            _ => Ok(()),
+        };
+        if self.keep == 0 {
+            ignore_not_found(std::fs::remove_file(&self.path))?;
+        } else {
+            ignore_not_found(std::fs::remove_file(self.rotated_path(self.keep)))?;
+            for index in (1..self.keep).rev() {
+                ignore_not_found(std::fs::rename(
+                    self.rotated_path(index),
+                    self.rotated_path(index + 1),
+                ))?;
+            }
+            std::fs::rename(&self.path, self.rotated_path(1))?;
+        }
+        self.reopen()
+    }
+
+    fn needs_rotation(&self, line_len: u64, now: SystemTime) -> bool {
+        let too_large = self
+            .rotate_size
+            .map_or(false, |max| self.size > 0 && self.size + line_len > max);
+        let too_old = self.rotate_interval.map_or(false, |interval| {
+            now.duration_since(self.opened_at).unwrap_or_default() >= interval
+        });
+        too_large || too_old
+    }
+
+    fn write_line(&mut self, line: &str) -> io::Result<()> {
+        if self.needs_rotation(line.len() as u64, SystemTime::now()) {
+            self.rotate()?;
+        }
+        self.file.write_all(line.as_bytes())?;
+        self.size += line.len() as u64;
+        Ok(())
+    }
+}
+
+#[derive(Debug)]
+enum Output {
+    Stderr,
+    File(Mutex<RotatingFile>),
+    #[cfg(unix)]
+    Syslog(Syslog),
+}
+
+#[derive(Debug)]
+pub struct Logger {
+    default_level: LevelFilter,
+    /// Module prefixes and their levels, the longest first
+    levels: Vec<(String, LevelFilter)>,
+    output: Output,
+}
+
+impl Logger {
+    fn new(
+        default_level: LevelFilter,
+        levels: HashMap<String, LevelFilter>,
+        output: Output,
+    ) -> Self {
+        let mut levels: Vec<_> = levels.into_iter().collect();
+        // Longest module prefix first
+        levels.sort_by(|(a, _), (b, _)| b.len().cmp(&a.len()).then_with(|| a.cmp(b)));
+        Self {
+            default_level,
+            levels,
+            output,
+        }
+    }
+
+    fn level(&self, target: &str) -> LevelFilter {
+        self.levels
+            .iter()
+            .find(|(module, _)| {
+                target
+                    .strip_prefix(module.as_str())
+                    .map_or(false, |rest| rest.is_empty() || rest.starts_with("::"))
+            })
+            .map_or(self.default_level, |(_, level)| *level)
+    }
+
+    fn max_level(&self) -> LevelFilter {
+        self.levels
+            .iter()
+            .map(|(_, level)| *level)
+            .fold(self.default_level, std::cmp::max)
+    }
+
+    /// Reopen log file, no-op for other outputs
+    pub fn reopen(&self) {
+        if let Output::File(file) = &self.output {
+            if let Err(e) = file.lock().unwrap().reopen() {
+                eprintln!("Cannot reopen log file: {e}");
+            }
+        }
+    }
+}
+
+impl Log for Logger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= self.level(metadata.target())
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+        let result = match &self.output {
+            Output::Stderr => writeln!(
+                io::stderr().lock(),
+                "{:<5} [{}] {}",
+                record.level(),
+                record.target(),
+                record.args()
+            ),
+            Output::File(file) => {
+                let line = format!(
+                    "{} {:<5} [{}] {}\n",
+                    rfc3339(SystemTime::now()),
+                    record.level(),
+                    record.target(),
+                    record.args()
+                );
+                file.lock().unwrap().write_line(&line)
+            }
+            #[cfg(unix)]
+            Output::Syslog(syslog) => syslog.send(record),
+        };
+        if let Err(e) = result {
+            eprintln!("Cannot write log record: {e}");
+        }
+    }
+
+    fn flush(&self) {}
+}
+
+#[derive(Error, Debug)]
+pub enum LoggingError {
+    #[error(r#"logging.path is required for "file" target"#)]
+    NoPath,
+    #[error("cannot open log file {path:?}: {error}")]
+    Open { path: PathBuf, error: io::Error },
+    #[cfg(unix)]
+    #[error("cannot create syslog socket: {0}")]
+    Syslog(io::Error),
+    #[error(transparent)]
+    SetLogger(#[from] log::SetLoggerError),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn logger(levels: &[(&str, LevelFilter)]) -> Logger {
+        let levels = levels
+            .iter()
+            .map(|(module, level)| (module.to_string(), *level))
+            .collect();
+        Logger::new(LevelFilter::Info, levels, Output::Stderr)
+    }
+
+    #[test]
+    fn module_levels() {
+        let logger = logger(&[
+            ("geo302::healthcheck", LevelFilter::Warn),
+            ("geo302::geo", LevelFilter::Debug),
+            ("geo302::geo::ripe_geo", LevelFilter::Error),
+        ]);
+        assert_eq!(logger.level("geo302::service"), LevelFilter::Info);
+        assert_eq!(logger.level("geo302::healthcheck"), LevelFilter::Warn);
+        assert_eq!(logger.level("geo302::healthcheckx"), LevelFilter::Info);
+        assert_eq!(logger.level("geo302::geo::maxmind"), LevelFilter::Debug);
+        assert_eq!(
+            logger.level("geo302::geo::ripe_geo::updater"),
+            LevelFilter::Error
+        );
+        assert_eq!(logger.max_level(), LevelFilter::Debug);
+    }
+
+    #[test]
+    fn config() {
+        let config: LoggingConfig = toml::from_str(
+            r#"
+            target = "file"
+            path = "/var/log/geo302.log"
+            rotate_size = 1000000
+            levels = { "geo302::healthcheck" = "warn" }
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.target, LogTarget::File);
+        assert_eq!(config.keep, 5);
+        assert_eq!(config.levels["geo302::healthcheck"], LevelFilter::Warn);
+    }
+
+    #[test]
+    fn rotation() {
+        let dir = std::env::temp_dir().join(format!("geo302-log-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("geo302.log");
+        let mut file = RotatingFile::open(path.clone(), Some(10), None, 2).unwrap();
+        for line in ["first\n", "second\n", "third\n", "fourth\n"] {
+            file.write_line(line).unwrap();
+        }
+        let read = |path: &Path| std::fs::read_to_string(path).unwrap();
+        assert_eq!(read(&path), "fourth\n");
+        assert_eq!(read(&file.rotated_path(1)), "third\n");
+        assert_eq!(read(&file.rotated_path(2)), "second\n");
+        assert!(!file.rotated_path(3).exists());
+
+        // Reopen after the file is moved away
+        std::fs::rename(&path, dir.join("moved.log")).unwrap();
+        file.reopen().unwrap();
+        file.write_line("fifth\n").unwrap();
+        assert_eq!(read(&path), "fifth\n");
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn interval_rotation() {
+        let path = std::env::temp_dir().join(format!("geo302-interval-{}.log", std::process::id()));
+        let mut file =
+            RotatingFile::open(path.clone(), None, Some(Duration::from_secs(60)), 1).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        file.opened_at = SystemTime::UNIX_EPOCH;
+        assert!(file.needs_rotation(1, SystemTime::UNIX_EPOCH + Duration::from_secs(60)));
+        assert!(!file.needs_rotation(1, SystemTime::UNIX_EPOCH + Duration::from_secs(59)));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn syslog_message() {
+        let syslog = Syslog {
+            socket: UnixDatagram::unbound().unwrap(),
+            path: "/dev/log".into(),
+            facility: SyslogFacility::Local0,
+            process_id: 42,
+        };
+        let time = SystemTime::UNIX_EPOCH + Duration::from_millis(971186136123);
+        let message = syslog.message(
+            time,
+            &Record::builder()
+                .level(Level::Warn)
+                .target("geo302::healthcheck")
+                .args(format_args!("mirror is unavailable"))
+                .build(),
+        );
+        assert_eq!(
+            message,
+            "<132>1 2000-10-10T13:55:36.123Z - geo302 42 - - [geo302::healthcheck] mirror is unavailable"
+        );
+    }
+}
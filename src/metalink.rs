@@ -0,0 +1,217 @@
+//! RFC 6249 Link headers and RFC 5854 Metalink documents listing alternative mirrors
+use hyper::header::{HeaderMap, HeaderValue, ACCEPT};
+use hyper::Uri;
+use std::fmt::Write;
+
+pub const METALINK_CONTENT_TYPE: &str = "application/metalink4+xml";
+const METALINK_SUFFIX: &str = ".meta4";
+
+/// Alternative location of the requested file
+#[derive(Debug)]
+pub struct Duplicate<'a> {
+    pub uri: Uri,
+    /// Lower values are preferred, starting from 1
+    pub priority: usize,
+    /// ISO 3166-1 alpha-2 country code
+    pub geo: Option<&'a str>,
+}
+
+impl<'a> Duplicate<'a> {
+    /// Value of "Link" header
+    pub fn link_header(&self) -> HeaderValue {
+        let mut value = format!("<{}>; rel=duplicate; pri={}", self.uri, self.priority);
+        if let Some(geo) = self.geo {
+            write!(value, "; geo={geo}").unwrap();
+        }
+        // Uri and country code are always valid header values
+        HeaderValue::try_from(value).unwrap()
+    }
+}
+
+/// Metalink is requested by `Accept` header or, if `suffix` is enabled, by ".meta4" suffix of
+/// the file path. `Accept` header wins, so a real "*.meta4" file can still be requested with it.
+///
+/// Returns the file path with the suffix stripped or `None` if Metalink is not requested.
+pub fn metalink_request<'a>(headers: &HeaderMap, path: &'a str, suffix: bool) -> Option<&'a str> {
+    let accepted = headers
+        .get_all(ACCEPT)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .filter_map(|media_range| media_range.split(';').next())
+        .any(|media_type| {
+            media_type
+                .trim()
+                .eq_ignore_ascii_case(METALINK_CONTENT_TYPE)
+        });
+    if accepted {
+        return Some(path);
+    }
+    path.strip_suffix(METALINK_SUFFIX)
+        .filter(|path| suffix && file_name(path).is_some())
+}
+
+/// Percent-decoded file name for Metalink document, the last path segment, `None` for
+/// directories
+pub fn file_name(path: &str) -> Option<String> {
+    let name = percent_decode(path.rsplit('/').next()?);
+    // Encoded slashes must not make a path of the name
+    let name = name.rsplit('/').next().unwrap_or_default();
+    (!matches!(name, "" | "." | "..")).then(|| name.to_owned())
+}
+
+/// Invalid escapes are kept as is, invalid UTF-8 is replaced
+fn percent_decode(s: &str) -> String {
+    let bytes = s.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let escaped = (bytes[i] == b'%')
+            .then(|| bytes.get(i + 1..i + 3))
+            .flatten()
+            .and_then(|hex| u8::from_str_radix(std::str::from_utf8(hex).ok()?, 16).ok());
+        match escaped {
+            Some(byte) => {
+                decoded.push(byte);
+                i += 3;
+            }
+            None => {
+                decoded.push(bytes[i]);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+/// Metalink v4 XML document for a single file
+pub fn metalink_document(file_name: &str, duplicates: &[Duplicate]) -> String {
+    let mut document = String::from(concat!(
+        r#"<?xml version="1.0" encoding="UTF-8"?>"#,
+        "\n",
+        r#"<metalink xmlns="urn:ietf:params:xml:ns:metalink">"#,
+        "\n"
+    ));
+    writeln!(document, r#"  <file name="{}">"#, xml_escape(file_name)).unwrap();
+    for duplicate in duplicates {
+        let location = duplicate
+            .geo
+            .map(|geo| format!(r#" location="{geo}""#))
+            .unwrap_or_default();
+        writeln!(
+            document,
+            r#"    <url{location} priority="{}">{}</url>"#,
+            duplicate.priority,
+            xml_escape(&duplicate.uri.to_string()),
+        )
+        .unwrap();
+    }
+    document.push_str("  </file>\n</metalink>\n");
+    document
+}
+
+fn xml_escape(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&apos;"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn link_header() {
+        let duplicate = Duplicate {
+            uri: "https://example.org/file".parse().unwrap(),
+            priority: 2,
+            geo: Some("de"),
+        };
+        assert_eq!(
+            duplicate.link_header(),
+            "<https://example.org/file>; rel=duplicate; pri=2; geo=de"
+        );
+        let duplicate = Duplicate {
+            geo: None,
+            ..duplicate
+        };
+        assert_eq!(
+            duplicate.link_header(),
+            "<https://example.org/file>; rel=duplicate; pri=2"
+        );
+    }
+
+    #[test]
+    fn detect_metalink_request() {
+        let mut headers = HeaderMap::new();
+        assert_eq!(metalink_request(&headers, "/file", true), None);
+        assert_eq!(
+            metalink_request(&headers, "/file.meta4", true),
+            Some("/file")
+        );
+        assert_eq!(metalink_request(&headers, "/file.meta4", false), None);
+        assert_eq!(metalink_request(&headers, "/.meta4", true), None);
+        assert_eq!(metalink_request(&headers, "/dir/.meta4", true), None);
+        headers.insert(
+            ACCEPT,
+            "text/html, Application/Metalink4+XML;q=0.9"
+                .parse()
+                .unwrap(),
+        );
+        assert_eq!(metalink_request(&headers, "/file", false), Some("/file"));
+        // Metalink of the real "*.meta4" file
+        assert_eq!(
+            metalink_request(&headers, "/file.meta4", true),
+            Some("/file.meta4")
+        );
+    }
+
+    #[test]
+    fn file_names() {
+        assert_eq!(file_name("/dir/file.fits").as_deref(), Some("file.fits"));
+        assert_eq!(file_name("/dir/"), None);
+        assert_eq!(
+            file_name("/dir/my%20file%C3%A9.fits").as_deref(),
+            Some("my fileé.fits")
+        );
+        assert_eq!(file_name("/dir/100%.fits").as_deref(), Some("100%.fits"));
+        assert_eq!(file_name("/dir/..%2Fpasswd").as_deref(), Some("passwd"));
+        assert_eq!(file_name("/dir/%2E%2E"), None);
+    }
+
+    #[test]
+    fn document() {
+        let duplicates = [
+            Duplicate {
+                uri: "https://a.example.org/file?a=1&b=2".parse().unwrap(),
+                priority: 1,
+                geo: Some("us"),
+            },
+            Duplicate {
+                uri: "https://b.example.org/file".parse().unwrap(),
+                priority: 2,
+                geo: None,
+            },
+        ];
+        assert_eq!(
+            metalink_document("file<1>", &duplicates),
+            r#"<?xml version="1.0" encoding="UTF-8"?>
+<metalink xmlns="urn:ietf:params:xml:ns:metalink">
+  <file name="file&lt;1&gt;">
+    <url location="us" priority="1">https://a.example.org/file?a=1&amp;b=2</url>
+    <url priority="2">https://b.example.org/file</url>
+  </file>
+</metalink>
+"#
+        );
+    }
+}
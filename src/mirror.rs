@@ -1,42 +1,119 @@
 use crate::geo::Continent;
+use crate::rewrite::{rewrite_path, RewriteRule};
+use crate::uri_tools::{compose_uri, parse_absolute_uri, AbsoluteUriError};
 
-use hyper::http::uri::{InvalidUri, Uri};
-use serde::Deserialize;
+use hyper::http::uri::Uri;
+use serde::{Deserialize, Deserializer};
 use smallvec::SmallVec;
 use std::collections::HashMap;
 use std::ops::Deref;
-use std::sync::atomic::AtomicBool;
-use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime};
 use thiserror::Error;
 
-#[derive(Debug, Deserialize)]
-#[serde(try_from = "MirrorConfig")]
+/// Result of the latest health checks
+#[derive(Debug, Clone, Default)]
+pub struct HealthStatus {
+    pub checked_at: Option<SystemTime>,
+    /// Time when availability changed last time
+    pub changed_at: Option<SystemTime>,
+    pub response_time: Option<Duration>,
+    /// Error or unsuccessful HTTP status of the latest check
+    pub error: Option<String>,
+}
+
+#[derive(Debug)]
 pub struct MirrorImpl {
+    /// Key of the mirror in the config
+    pub name: String,
     pub upstream: Uri,
     pub healthcheck: Uri,
     pub available: AtomicBool,
+    /// Request path rewrite rules, applied in order
+    pub rewrite: Vec<RewriteRule>,
+    /// Whether to pass request query string to the mirror
+    pub forward_query: bool,
+    /// Lower-case ISO 3166-1 alpha-2 country code of the mirror location
+    pub geo: Option<String>,
+    health: Mutex<HealthStatus>,
 }
 
 #[derive(Debug, Deserialize)]
 struct MirrorConfig {
     upstream: String,
     healthcheck: String,
+    #[serde(default)]
+    rewrite: Vec<RewriteRule>,
+    #[serde(default = "MirrorConfig::default_forward_query")]
+    forward_query: bool,
+    #[serde(default)]
+    geo: Option<String>,
+}
+
+impl MirrorConfig {
+    fn default_forward_query() -> bool {
+        true
+    }
 }
 
-impl TryFrom<MirrorConfig> for MirrorImpl {
-    type Error = InvalidUri;
+#[derive(Error, Debug)]
+pub enum MirrorConfigError {
+    #[error(transparent)]
+    Uri(#[from] AbsoluteUriError),
+    #[error(r#"geo must be a two-letter country code, got "{0}""#)]
+    InvalidGeo(String),
+}
 
-    fn try_from(value: MirrorConfig) -> Result<Self, Self::Error> {
+impl MirrorImpl {
+    fn from_config(name: String, value: MirrorConfig) -> Result<Self, MirrorConfigError> {
+        let geo = match value.geo {
+            Some(geo) if geo.len() == 2 && geo.bytes().all(|c| c.is_ascii_alphabetic()) => {
+                Some(geo.to_ascii_lowercase())
+            }
+            Some(geo) => return Err(MirrorConfigError::InvalidGeo(geo)),
+            None => None,
+        };
         Ok(Self {
-            upstream: value.upstream.as_str().try_into()?,
-            healthcheck: value.healthcheck.as_str().try_into()?,
+            name,
+            upstream: parse_absolute_uri(&value.upstream)?,
+            healthcheck: parse_absolute_uri(&value.healthcheck)?,
             available: AtomicBool::new(false),
+            rewrite: value.rewrite,
+            forward_query: value.forward_query,
+            geo,
+            health: Mutex::new(HealthStatus::default()),
         })
     }
+
+    /// Mirror URI for the request path and query
+    pub fn location(&self, path: &str, query: Option<&str>) -> Result<Uri, hyper::http::Error> {
+        let path = rewrite_path(&self.rewrite, path);
+        let query = query.filter(|_| self.forward_query);
+        compose_uri(&self.upstream, &path, query)
+    }
+
+    /// Store health check result, `error` is `None` for available mirrors
+    pub fn update_health(&self, response_time: Option<Duration>, error: Option<String>) {
+        let available = error.is_none();
+        let now = SystemTime::now();
+        let mut health = self.health.lock().unwrap();
+        if self.available.swap(available, Ordering::AcqRel) != available
+            || health.changed_at.is_none()
+        {
+            health.changed_at = Some(now);
+        }
+        health.checked_at = Some(now);
+        health.response_time = response_time;
+        health.error = error;
+    }
+
+    pub fn health(&self) -> HealthStatus {
+        self.health.lock().unwrap().clone()
+    }
 }
 
-#[derive(Debug, Clone, Deserialize)]
-#[serde(from = "MirrorImpl")]
+#[derive(Debug, Clone)]
 pub struct Mirror(Arc<MirrorImpl>);
 
 impl AsRef<MirrorImpl> for Mirror {
@@ -59,6 +136,21 @@ impl From<MirrorImpl> for Mirror {
     }
 }
 
+/// Deserialize mirror table of the config, keys are used as mirror names
+pub fn deserialize_mirrors<'de, D>(deserializer: D) -> Result<HashMap<String, Mirror>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    HashMap::<String, MirrorConfig>::deserialize(deserializer)?
+        .into_iter()
+        .map(|(name, config)| {
+            let mirror = MirrorImpl::from_config(name.clone(), config)
+                .map_err(|error| serde::de::Error::custom(format!("mirror {name}: {error}")))?;
+            Ok((name, mirror.into()))
+        })
+        .collect()
+}
+
 pub type MirrorVec = SmallVec<[Mirror; 4]>;
 
 #[derive(Debug, Clone)]
@@ -112,6 +204,11 @@ impl ContinentMap {
             .unwrap_or_else(|| self.get_default())
     }
 
+    /// Mirrors of the continent without falling back to the default ones
+    pub fn get_exact(&self, continent: Continent) -> Option<&MirrorVec> {
+        self.map.get(&continent)
+    }
+
     pub fn get_default(&self) -> &MirrorVec {
         self.map.get(&Continent::Default).unwrap()
     }
@@ -142,6 +239,7 @@ mod tests {
 
     #[derive(Debug, Deserialize)]
     struct MirrorsContinentsConfig {
+        #[serde(deserialize_with = "deserialize_mirrors")]
         mirrors: HashMap<String, Mirror>,
         continents: HashMap<String, Vec<String>>,
     }
@@ -230,6 +328,93 @@ mod tests {
         ));
     }
 
+    #[test]
+    fn mirror_location() {
+        let s = r#"
+        [mirrors]
+        a = { upstream = "http://a.example.com/mirror", healthcheck = "http://a.example.com/ping" }
+        b = { upstream = "http://b.example.com/data", healthcheck = "http://b.example.com/ping", rewrite = [{ strip_prefix = "/pub" }], forward_query = false }
+
+        [continents]
+        default = ["a", "b"]
+        "#;
+        let config: MirrorsContinentsConfig = toml::from_str(s).unwrap();
+        let location = |name: &str| {
+            config.mirrors[name]
+                .location("/pub/ztf/file", Some("a=1"))
+                .unwrap()
+                .to_string()
+        };
+        assert_eq!(
+            location("a"),
+            "http://a.example.com/mirror/pub/ztf/file?a=1"
+        );
+        assert_eq!(location("b"), "http://b.example.com/data/ztf/file");
+    }
+
+    #[test]
+    fn mirror_location_join() {
+        let s = r#"
+        [mirrors]
+        a = { upstream = "http://a.example.com/mirror/", healthcheck = "http://a.example.com/ping" }
+        b = { upstream = "http://b.example.com", healthcheck = "http://b.example.com/ping", rewrite = [{ strip_prefix = "/pub" }], forward_query = false }
+        c = { upstream = "http://c.example.com/?token=x", healthcheck = "http://c.example.com/ping" }
+
+        [continents]
+        default = ["a", "b", "c"]
+        "#;
+        let config: MirrorsContinentsConfig = toml::from_str(s).unwrap();
+        let location = |name: &str| {
+            config.mirrors[name]
+                .location("/pub/ztf/file", Some("a=1"))
+                .unwrap()
+                .to_string()
+        };
+        // Trailing slash of the upstream is not doubled
+        assert_eq!(
+            location("a"),
+            "http://a.example.com/mirror/pub/ztf/file?a=1"
+        );
+        // Upstream without path
+        assert_eq!(location("b"), "http://b.example.com/ztf/file");
+        // Upstream query goes first
+        assert_eq!(
+            location("c"),
+            "http://c.example.com/pub/ztf/file?token=x&a=1"
+        );
+    }
+
+    #[test]
+    fn relative_upstream() {
+        let s = r#"
+        [mirrors]
+        mirror = { upstream = "/pub", healthcheck = "http://example.com/ping" }
+
+        [continents]
+        default = ["mirror"]
+        "#;
+        let error = toml::from_str::<MirrorsContinentsConfig>(s).unwrap_err();
+        assert!(error.to_string().contains("must be absolute"), "{error}");
+    }
+
+    #[test]
+    fn mirror_geo() {
+        let s = r#"
+        [mirrors]
+        a = { upstream = "http://a.example.com", healthcheck = "http://a.example.com/ping", geo = "DE" }
+        b = { upstream = "http://b.example.com", healthcheck = "http://b.example.com/ping" }
+
+        [continents]
+        default = ["a", "b"]
+        "#;
+        let config: MirrorsContinentsConfig = toml::from_str(s).unwrap();
+        assert_eq!(config.mirrors["a"].geo.as_deref(), Some("de"));
+        assert_eq!(config.mirrors["b"].geo, None);
+
+        let error = toml::from_str::<MirrorsContinentsConfig>(&s.replace("DE", "DEU")).unwrap_err();
+        assert!(error.to_string().contains("two-letter"), "{error}");
+    }
+
     #[test]
     fn wrong_continent_name() {
         let s = r#"
@@ -0,0 +1,217 @@
+//! Endpoint listing healthy mirrors for clients doing their own mirror selection and failover
+use crate::geo::Continent;
+use crate::mirror::Mirror;
+
+use hyper::header::{HeaderMap, ACCEPT};
+use serde::Serialize;
+use std::fmt::Write;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+pub const MIRROR_LIST_PATH: &str = "/.well-known/geo302/mirrors";
+
+/// Requested file path for the mirror list endpoint, e.g. "/ztf/file" for
+/// "/.well-known/geo302/mirrors/ztf/file", `None` for other paths
+pub fn mirror_list_request(path: &str) -> Option<&str> {
+    match path.strip_prefix(MIRROR_LIST_PATH)? {
+        "" => Some("/"),
+        rest if rest.starts_with('/') => Some(rest),
+        _ => None,
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MirrorListFormat {
+    Json,
+    /// Mirror URLs, one per line
+    Text,
+}
+
+impl MirrorListFormat {
+    /// Select format by "format" query parameter or `Accept` header, JSON is the default
+    pub fn from_request(query: Option<&str>, headers: &HeaderMap) -> Self {
+        let format_parameter = query
+            .into_iter()
+            .flat_map(|query| query.split('&'))
+            .find_map(|pair| pair.strip_prefix("format="));
+        match format_parameter {
+            Some("text") => return Self::Text,
+            Some("json") => return Self::Json,
+            _ => {}
+        }
+        let accepts_text = headers
+            .get_all(ACCEPT)
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(','))
+            .filter_map(|media_range| media_range.split(';').next())
+            .any(|media_type| media_type.trim().eq_ignore_ascii_case("text/plain"));
+        if accepts_text {
+            Self::Text
+        } else {
+            Self::Json
+        }
+    }
+
+    pub fn content_type(self) -> &'static str {
+        match self {
+            Self::Json => "application/json",
+            Self::Text => "text/plain; charset=utf-8",
+        }
+    }
+}
+
+#[derive(Serialize, Debug)]
+struct MirrorHealth {
+    available: bool,
+    /// Unix timestamps in seconds
+    checked_at: Option<u64>,
+    changed_at: Option<u64>,
+    response_time_ms: Option<u128>,
+    error: Option<String>,
+}
+
+#[derive(Serialize, Debug)]
+struct MirrorListEntry<'a> {
+    name: &'a str,
+    url: String,
+    geo: Option<&'a str>,
+    health: MirrorHealth,
+}
+
+/// Healthy mirrors in the order they are considered for redirects
+#[derive(Serialize, Debug)]
+pub struct MirrorList<'a> {
+    /// `None` if client location is unknown
+    continent: Option<String>,
+    path: &'a str,
+    mirrors: Vec<MirrorListEntry<'a>>,
+}
+
+impl<'a> MirrorList<'a> {
+    pub fn new(continent: Option<Continent>, path: &'a str) -> Self {
+        Self {
+            continent: continent.map(|continent| format!("{continent:?}")),
+            path,
+            mirrors: vec![],
+        }
+    }
+
+    pub fn push(&mut self, mirror: &'a Mirror, url: String) {
+        let health = mirror.health();
+        self.mirrors.push(MirrorListEntry {
+            name: &mirror.name,
+            url,
+            geo: mirror.geo.as_deref(),
+            health: MirrorHealth {
+                available: health.error.is_none() && health.checked_at.is_some(),
+                checked_at: health.checked_at.and_then(unix_timestamp),
+                changed_at: health.changed_at.and_then(unix_timestamp),
+                response_time_ms: health.response_time.map(|duration| duration.as_millis()),
+                error: health.error,
+            },
+        });
+    }
+
+    pub fn render(&self, format: MirrorListFormat) -> String {
+        match format {
+            MirrorListFormat::Json => serde_json::to_string(self).unwrap(),
+            MirrorListFormat::Text => {
+                self.mirrors.iter().fold(String::new(), |mut text, mirror| {
+                    writeln!(text, "{}", mirror.url).unwrap();
+                    text
+                })
+            }
+        }
+    }
+}
+
+fn unix_timestamp(time: SystemTime) -> Option<u64> {
+    time.duration_since(UNIX_EPOCH)
+        .ok()
+        .map(|duration| duration.as_secs())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn request_path() {
+        assert_eq!(
+            mirror_list_request("/.well-known/geo302/mirrors"),
+            Some("/")
+        );
+        assert_eq!(
+            mirror_list_request("/.well-known/geo302/mirrors/ztf/file"),
+            Some("/ztf/file")
+        );
+        assert_eq!(mirror_list_request("/.well-known/geo302/mirrorsx"), None);
+        assert_eq!(mirror_list_request("/ztf/file"), None);
+    }
+
+    #[test]
+    fn format() {
+        let mut headers = HeaderMap::new();
+        assert_eq!(
+            MirrorListFormat::from_request(None, &headers),
+            MirrorListFormat::Json
+        );
+        assert_eq!(
+            MirrorListFormat::from_request(Some("a=1&format=text"), &headers),
+            MirrorListFormat::Text
+        );
+        headers.insert(ACCEPT, "text/plain".parse().unwrap());
+        assert_eq!(
+            MirrorListFormat::from_request(None, &headers),
+            MirrorListFormat::Text
+        );
+        assert_eq!(
+            MirrorListFormat::from_request(Some("format=json"), &headers),
+            MirrorListFormat::Json
+        );
+    }
+
+    #[test]
+    fn render() {
+        let list = MirrorList::new(Some(Continent::NorthAmerica), "/file");
+        assert_eq!(
+            list.render(MirrorListFormat::Json),
+            r#"{"continent":"NorthAmerica","path":"/file","mirrors":[]}"#
+        );
+        assert_eq!(list.render(MirrorListFormat::Text), "");
+    }
+
+    #[test]
+    fn render_mirror() {
+        #[derive(serde::Deserialize)]
+        struct Mirrors {
+            #[serde(deserialize_with = "crate::mirror::deserialize_mirrors")]
+            mirrors: std::collections::HashMap<String, Mirror>,
+        }
+        let Mirrors { mirrors } = toml::from_str(
+            r#"
+            [mirrors]
+            a = { upstream = "http://a.example.com", healthcheck = "http://a.example.com/ping", geo = "us" }
+            "#,
+        )
+        .unwrap();
+        let mirror = &mirrors["a"];
+        mirror.update_health(Some(std::time::Duration::from_millis(12)), None);
+
+        let mut list = MirrorList::new(None, "/file");
+        list.push(mirror, "http://a.example.com/file".to_owned());
+        assert_eq!(
+            list.render(MirrorListFormat::Text),
+            "http://a.example.com/file\n"
+        );
+        let json: serde_json::Value =
+            serde_json::from_str(&list.render(MirrorListFormat::Json)).unwrap();
+        assert_eq!(json["continent"], serde_json::Value::Null);
+        let entry = &json["mirrors"][0];
+        assert_eq!(entry["name"], "a");
+        assert_eq!(entry["geo"], "us");
+        assert_eq!(entry["health"]["available"], true);
+        assert_eq!(entry["health"]["response_time_ms"], 12);
+        assert!(entry["health"]["checked_at"].is_u64());
+    }
+}
@@ -0,0 +1,259 @@
+//! Parser of HAProxy PROXY protocol v1 and v2 headers
+//! https://www.haproxy.org/download/2.8/doc/proxy-protocol.txt
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use thiserror::Error;
+use tokio::io::{AsyncRead, AsyncReadExt};
+
+const V2_SIGNATURE: [u8; 12] = *b"\r\n\r\n\0\r\nQUIT\n";
+const V1_PREFIX: &[u8] = b"PROXY ";
+/// Maximum length of v1 header including CRLF
+const V1_MAX_LENGTH: usize = 107;
+
+#[derive(Error, Debug)]
+pub enum ProxyProtocolError {
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+    #[error("Connection doesn't start with PROXY protocol header")]
+    NoHeader,
+    #[error("PROXY protocol v1 header is invalid")]
+    InvalidV1,
+    #[error("PROXY protocol v2 header is invalid")]
+    InvalidV2,
+}
+
+/// Read PROXY protocol header from the very beginning of the stream and return the source address.
+/// The stream is left positioned right after the header.
+///
+/// `None` is returned for health checks of the proxy itself (LOCAL and UNKNOWN) and for address
+/// families we don't know how to represent, in these cases the socket address should be used.
+pub async fn read_header<R>(reader: &mut R) -> Result<Option<SocketAddr>, ProxyProtocolError>
+where
+    R: AsyncRead + Unpin,
+{
+    // v1 header is at least 15 bytes long, so we can always read the v2 signature length
+    let mut buf = vec![0u8; V2_SIGNATURE.len()];
+    reader.read_exact(&mut buf).await?;
+
+    if buf == V2_SIGNATURE {
+        let mut header = [0u8; 4];
+        reader.read_exact(&mut header).await?;
+        let length = u16::from_be_bytes([header[2], header[3]]) as usize;
+        let mut payload = vec![0u8; length];
+        reader.read_exact(&mut payload).await?;
+        return parse_v2(header[0], header[1], &payload);
+    }
+
+    if !buf.starts_with(V1_PREFIX) {
+        return Err(ProxyProtocolError::NoHeader);
+    }
+    // Read byte by byte to not consume anything after the header
+    while !buf.ends_with(b"\r\n") {
+        if buf.len() == V1_MAX_LENGTH {
+            return Err(ProxyProtocolError::InvalidV1);
+        }
+        buf.push(reader.read_u8().await?);
+    }
+    parse_v1(&buf)
+}
+
+/// Parse full v1 header line including "PROXY " prefix and CRLF
+fn parse_v1(line: &[u8]) -> Result<Option<SocketAddr>, ProxyProtocolError> {
+    let line = std::str::from_utf8(line)
+        .ok()
+        .and_then(|line| line.strip_suffix("\r\n"))
+        .ok_or(ProxyProtocolError::InvalidV1)?;
+    let mut parts = line.split(' ').skip(1);
+    let is_ipv4 = match parts.next() {
+        Some("UNKNOWN") => return Ok(None),
+        Some("TCP4") => true,
+        Some("TCP6") => false,
+        _ => return Err(ProxyProtocolError::InvalidV1),
+    };
+    let parts: Vec<_> = parts.collect();
+    if parts.len() != 4 {
+        return Err(ProxyProtocolError::InvalidV1);
+    }
+    let ip: IpAddr = parts[0]
+        .parse()
+        .map_err(|_| ProxyProtocolError::InvalidV1)?;
+    if ip.is_ipv4() != is_ipv4 {
+        return Err(ProxyProtocolError::InvalidV1);
+    }
+    // Validate destination address and port too, a malformed header must not be accepted
+    let _: IpAddr = parts[1]
+        .parse()
+        .map_err(|_| ProxyProtocolError::InvalidV1)?;
+    let port: u16 = parts[2]
+        .parse()
+        .map_err(|_| ProxyProtocolError::InvalidV1)?;
+    let _: u16 = parts[3]
+        .parse()
+        .map_err(|_| ProxyProtocolError::InvalidV1)?;
+    Ok(Some(SocketAddr::new(ip, port)))
+}
+
+/// Parse v2 header after the signature: version-command byte, family-protocol byte and the payload
+fn parse_v2(
+    version_command: u8,
+    family_protocol: u8,
+    payload: &[u8],
+) -> Result<Option<SocketAddr>, ProxyProtocolError> {
+    if version_command >> 4 != 2 {
+        return Err(ProxyProtocolError::InvalidV2);
+    }
+    match version_command & 0x0F {
+        // LOCAL
+        0x0 => return Ok(None),
+        // PROXY
+        0x1 => {}
+        _ => return Err(ProxyProtocolError::InvalidV2),
+    }
+    match family_protocol >> 4 {
+        // AF_INET
+        0x1 => {
+            if payload.len() < 12 {
+                return Err(ProxyProtocolError::InvalidV2);
+            }
+            let ip: [u8; 4] = payload[0..4].try_into().unwrap();
+            let port = u16::from_be_bytes([payload[8], payload[9]]);
+            Ok(Some(SocketAddr::new(Ipv4Addr::from(ip).into(), port)))
+        }
+        // AF_INET6
+        0x2 => {
+            if payload.len() < 36 {
+                return Err(ProxyProtocolError::InvalidV2);
+            }
+            let ip: [u8; 16] = payload[0..16].try_into().unwrap();
+            let port = u16::from_be_bytes([payload[32], payload[33]]);
+            Ok(Some(SocketAddr::new(Ipv6Addr::from(ip).into(), port)))
+        }
+        // AF_UNSPEC and AF_UNIX
+        0x0 | 0x3 => Ok(None),
+        _ => Err(ProxyProtocolError::InvalidV2),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn read(mut data: &[u8]) -> (Result<Option<SocketAddr>, ProxyProtocolError>, Vec<u8>) {
+        let result = read_header(&mut data).await;
+        let mut rest = vec![];
+        data.read_to_end(&mut rest).await.unwrap();
+        (result, rest)
+    }
+
+    #[tokio::test]
+    async fn v1_tcp4() {
+        let (result, rest) =
+            read(b"PROXY TCP4 192.0.2.1 198.51.100.1 56324 443\r\nGET / HTTP/1.1\r\n").await;
+        assert_eq!(result.unwrap(), Some("192.0.2.1:56324".parse().unwrap()));
+        assert_eq!(rest, b"GET / HTTP/1.1\r\n");
+    }
+
+    #[tokio::test]
+    async fn v1_tcp6() {
+        let (result, rest) = read(b"PROXY TCP6 2001:db8::1 2001:db8::2 4711 80\r\n").await;
+        assert_eq!(result.unwrap(), Some("[2001:db8::1]:4711".parse().unwrap()));
+        assert!(rest.is_empty());
+    }
+
+    #[tokio::test]
+    async fn v1_unknown() {
+        let (result, rest) = read(b"PROXY UNKNOWN\r\nGET").await;
+        assert_eq!(result.unwrap(), None);
+        assert_eq!(rest, b"GET");
+    }
+
+    #[tokio::test]
+    async fn v1_invalid() {
+        for data in [
+            &b"PROXY TCP4 192.0.2.1 198.51.100.1 56324\r\n"[..],
+            b"PROXY TCP4 192.0.2.1 198.51.100.1 56324 443 1\r\n",
+            b"PROXY TCP4 localhost 198.51.100.1 56324 443\r\n",
+            b"PROXY UDP4 192.0.2.1 198.51.100.1 56324 443\r\n",
+            b"PROXY TCP4 192.0.2.1 198.51.100.1 56324 443\n",
+            b"PROXY TCP4 2001:db8::1 2001:db8::2 4711 80\r\n",
+        ] {
+            let (result, _rest) = read(data).await;
+            assert!(result.is_err(), "{}", String::from_utf8_lossy(data));
+        }
+    }
+
+    #[tokio::test]
+    async fn v1_too_long() {
+        let mut data = b"PROXY ".to_vec();
+        data.extend(std::iter::repeat(b'1').take(200));
+        let (result, _rest) = read(&data).await;
+        assert!(matches!(result, Err(ProxyProtocolError::InvalidV1)));
+    }
+
+    #[tokio::test]
+    async fn no_header() {
+        let (result, _rest) = read(b"GET / HTTP/1.1\r\nHost: example.org\r\n").await;
+        assert!(matches!(result, Err(ProxyProtocolError::NoHeader)));
+    }
+
+    #[tokio::test]
+    async fn v2_tcp4() {
+        let mut data = V2_SIGNATURE.to_vec();
+        data.extend([0x21, 0x11, 0, 12]);
+        data.extend([192, 0, 2, 1, 198, 51, 100, 1]);
+        data.extend(56324u16.to_be_bytes());
+        data.extend(443u16.to_be_bytes());
+        data.extend(b"GET");
+        let (result, rest) = read(&data).await;
+        assert_eq!(result.unwrap(), Some("192.0.2.1:56324".parse().unwrap()));
+        assert_eq!(rest, b"GET");
+    }
+
+    #[tokio::test]
+    async fn v2_tcp6_with_tlv() {
+        let src: Ipv6Addr = "2001:db8::1".parse().unwrap();
+        let dst: Ipv6Addr = "2001:db8::2".parse().unwrap();
+        let mut data = V2_SIGNATURE.to_vec();
+        // 36 bytes of addresses and a 4-bytes NOOP TLV
+        data.extend([0x21, 0x21, 0, 40]);
+        data.extend(src.octets());
+        data.extend(dst.octets());
+        data.extend(4711u16.to_be_bytes());
+        data.extend(80u16.to_be_bytes());
+        data.extend([0x04, 0, 1, 0]);
+        data.extend(b"GET");
+        let (result, rest) = read(&data).await;
+        assert_eq!(result.unwrap(), Some(SocketAddr::new(src.into(), 4711)));
+        assert_eq!(rest, b"GET");
+    }
+
+    #[tokio::test]
+    async fn v2_local() {
+        let mut data = V2_SIGNATURE.to_vec();
+        data.extend([0x20, 0x00, 0, 0]);
+        data.extend(b"GET");
+        let (result, rest) = read(&data).await;
+        assert_eq!(result.unwrap(), None);
+        assert_eq!(rest, b"GET");
+    }
+
+    #[tokio::test]
+    async fn v2_invalid() {
+        for header in [
+            // wrong version
+            [0x11, 0x11, 0, 12],
+            // wrong command
+            [0x22, 0x11, 0, 12],
+            // too short payload for AF_INET
+            [0x21, 0x11, 0, 8],
+        ] {
+            let mut data = V2_SIGNATURE.to_vec();
+            data.extend(header);
+            data.extend([0u8; 12]);
+            let (result, _rest) = read(&data).await;
+            assert!(
+                matches!(result, Err(ProxyProtocolError::InvalidV2)),
+                "{header:?}"
+            );
+        }
+    }
+}
@@ -0,0 +1,103 @@
+use hyper::StatusCode;
+use serde::Deserialize;
+use std::collections::HashMap;
+use thiserror::Error;
+
+#[derive(Error, Debug, PartialEq, Eq)]
+#[error("Redirect status must be one of 301, 302, 303, 307, 308, got {0}")]
+pub struct InvalidRedirectStatus(u16);
+
+/// HTTP status code of a redirect response
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(try_from = "u16")]
+pub struct RedirectStatus(StatusCode);
+
+impl TryFrom<u16> for RedirectStatus {
+    type Error = InvalidRedirectStatus;
+
+    fn try_from(code: u16) -> Result<Self, Self::Error> {
+        match code {
+            301 | 302 | 303 | 307 | 308 => Ok(Self(StatusCode::from_u16(code).unwrap())),
+            _ => Err(InvalidRedirectStatus(code)),
+        }
+    }
+}
+
+impl From<RedirectStatus> for StatusCode {
+    fn from(status: RedirectStatus) -> Self {
+        status.0
+    }
+}
+
+impl Default for RedirectStatus {
+    fn default() -> Self {
+        Self(StatusCode::FOUND)
+    }
+}
+
+/// Redirect status selection by the longest matching path prefix
+#[derive(Debug, Clone, Default)]
+pub struct RedirectStatusMap {
+    default: RedirectStatus,
+    // Sorted by prefix length, the longest first
+    prefixes: Vec<(String, RedirectStatus)>,
+}
+
+impl RedirectStatusMap {
+    pub fn new(default: RedirectStatus, prefixes: HashMap<String, RedirectStatus>) -> Self {
+        let mut prefixes: Vec<_> = prefixes.into_iter().collect();
+        prefixes.sort_by(|(a, _), (b, _)| b.len().cmp(&a.len()).then_with(|| a.cmp(b)));
+        Self { default, prefixes }
+    }
+
+    pub fn get(&self, path: &str) -> StatusCode {
+        self.prefixes
+            .iter()
+            .find(|(prefix, _)| path.starts_with(prefix.as_str()))
+            .map_or(self.default, |(_, status)| *status)
+            .into()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn valid_statuses() {
+        for code in [301, 302, 303, 307, 308] {
+            let status: RedirectStatus = code.try_into().unwrap();
+            assert_eq!(StatusCode::from(status).as_u16(), code);
+        }
+    }
+
+    #[test]
+    fn invalid_statuses() {
+        for code in [200, 300, 304, 305, 404] {
+            assert_eq!(
+                RedirectStatus::try_from(code).unwrap_err(),
+                InvalidRedirectStatus(code)
+            );
+        }
+    }
+
+    #[test]
+    fn longest_prefix() {
+        let map = RedirectStatusMap::new(
+            302.try_into().unwrap(),
+            [
+                ("/upload/".to_owned(), 307.try_into().unwrap()),
+                ("/upload/pinned/".to_owned(), 308.try_into().unwrap()),
+            ]
+            .into_iter()
+            .collect(),
+        );
+        assert_eq!(map.get("/"), StatusCode::FOUND);
+        assert_eq!(map.get("/upload"), StatusCode::FOUND);
+        assert_eq!(map.get("/upload/file"), StatusCode::TEMPORARY_REDIRECT);
+        assert_eq!(
+            map.get("/upload/pinned/file"),
+            StatusCode::PERMANENT_REDIRECT
+        );
+    }
+}
@@ -0,0 +1,160 @@
+use regex::Regex;
+use serde::Deserialize;
+use std::borrow::Cow;
+use thiserror::Error;
+
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+struct RewriteRuleConfig {
+    #[serde(default)]
+    strip_prefix: Option<String>,
+    #[serde(default)]
+    add_prefix: Option<String>,
+    #[serde(default)]
+    regex: Option<String>,
+    #[serde(default)]
+    replace: Option<String>,
+}
+
+/// Request path rewrite rule applied before composing the mirror URI
+#[derive(Deserialize, Debug, Clone)]
+#[serde(try_from = "RewriteRuleConfig")]
+pub enum RewriteRule {
+    /// Remove the prefix if it is followed by "/" or is the whole path
+    StripPrefix(String),
+    AddPrefix(String),
+    /// Replace the first match, replacement could refer capture groups as `$1` or `$name`
+    Regex {
+        regex: Regex,
+        replace: String,
+    },
+}
+
+impl TryFrom<RewriteRuleConfig> for RewriteRule {
+    type Error = RewriteRuleConfigError;
+
+    fn try_from(config: RewriteRuleConfig) -> Result<Self, Self::Error> {
+        match config {
+            RewriteRuleConfig {
+                strip_prefix: Some(prefix),
+                add_prefix: None,
+                regex: None,
+                replace: None,
+            } => Ok(Self::StripPrefix(prefix)),
+            RewriteRuleConfig {
+                strip_prefix: None,
+                add_prefix: Some(prefix),
+                regex: None,
+                replace: None,
+            } => Ok(Self::AddPrefix(prefix)),
+            RewriteRuleConfig {
+                strip_prefix: None,
+                add_prefix: None,
+                regex: Some(regex),
+                replace: Some(replace),
+            } => Ok(Self::Regex {
+                regex: Regex::new(&regex)?,
+                replace,
+            }),
+            _ => Err(RewriteRuleConfigError::OneRule),
+        }
+    }
+}
+
+impl RewriteRule {
+    pub fn apply<'a>(&self, path: Cow<'a, str>) -> Cow<'a, str> {
+        match self {
+            Self::StripPrefix(prefix) => match path.strip_prefix(prefix.as_str()) {
+                Some("") => "/".into(),
+                Some(rest) if rest.starts_with('/') => rest.to_owned().into(),
+                _ => path,
+            },
+            Self::AddPrefix(prefix) => format!("{prefix}{path}").into(),
+            Self::Regex { regex, replace } => match regex.replace(&path, replace.as_str()) {
+                Cow::Borrowed(_) => path,
+                Cow::Owned(new_path) => new_path.into(),
+            },
+        }
+    }
+}
+
+/// Apply all rules one after another
+pub fn rewrite_path<'a>(rules: &[RewriteRule], path: &'a str) -> Cow<'a, str> {
+    rules
+        .iter()
+        .fold(Cow::Borrowed(path), |path, rule| rule.apply(path))
+}
+
+#[derive(Error, Debug)]
+pub enum RewriteRuleConfigError {
+    #[error(
+        r#"exactly one of "strip_prefix", "add_prefix" and "regex" with "replace" must be specified"#
+    )]
+    OneRule,
+    #[error(transparent)]
+    Regex(#[from] regex::Error),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Deserialize)]
+    struct Rules {
+        rewrite: Vec<RewriteRule>,
+    }
+
+    fn rules_from_str(s: &str) -> Result<Vec<RewriteRule>, toml::de::Error> {
+        toml::from_str::<Rules>(s).map(|rules| rules.rewrite)
+    }
+
+    #[test]
+    fn strip_prefix() {
+        let rules = rules_from_str(r#"rewrite = [{ strip_prefix = "/pub" }]"#).unwrap();
+        assert_eq!(rewrite_path(&rules, "/pub/ztf/file"), "/ztf/file");
+        assert_eq!(rewrite_path(&rules, "/pub"), "/");
+        assert_eq!(rewrite_path(&rules, "/public/file"), "/public/file");
+        assert_eq!(rewrite_path(&rules, "/ztf/file"), "/ztf/file");
+    }
+
+    #[test]
+    fn add_prefix() {
+        let rules = rules_from_str(r#"rewrite = [{ add_prefix = "/pub" }]"#).unwrap();
+        assert_eq!(rewrite_path(&rules, "/ztf/file"), "/pub/ztf/file");
+    }
+
+    #[test]
+    fn regex() {
+        let rules = rules_from_str(
+            r#"rewrite = [{ regex = "^/ztf/dr(?P<dr>\\d+)/(.*)$", replace = "/archive/${dr}/$2" }]"#,
+        )
+        .unwrap();
+        assert_eq!(
+            rewrite_path(&rules, "/ztf/dr17/lc/file.parquet"),
+            "/archive/17/lc/file.parquet"
+        );
+        assert_eq!(rewrite_path(&rules, "/sdss/file"), "/sdss/file");
+    }
+
+    #[test]
+    fn chain() {
+        let rules =
+            rules_from_str(r#"rewrite = [{ strip_prefix = "/pub" }, { add_prefix = "/mirror" }]"#)
+                .unwrap();
+        assert_eq!(rewrite_path(&rules, "/pub/ztf/file"), "/mirror/ztf/file");
+    }
+
+    #[test]
+    fn invalid_rules() {
+        for s in [
+            r#"rewrite = [{}]"#,
+            r#"rewrite = [{ strip_prefix = "/pub", add_prefix = "/data" }]"#,
+            r#"rewrite = [{ regex = "^/pub" }]"#,
+            r#"rewrite = [{ replace = "/data" }]"#,
+            r#"rewrite = [{ regex = "(", replace = "/data" }]"#,
+            r#"rewrite = [{ prefix = "/data" }]"#,
+        ] {
+            assert!(rules_from_str(s).is_err(), "{s}");
+        }
+    }
+}
@@ -0,0 +1,271 @@
+use crate::mirror::{ContinentMap, ContinentMapConfigError, Mirror};
+
+use serde::Deserialize;
+use std::collections::HashMap;
+use thiserror::Error;
+
+/// Request path pattern: `*` matches any characters except "/", `**` matches any characters,
+/// `?` matches a single character except "/"
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Glob(String);
+
+impl Glob {
+    pub fn new(pattern: impl Into<String>) -> Self {
+        Self(pattern.into())
+    }
+
+    pub fn matches(&self, path: &str) -> bool {
+        Self::matches_bytes(self.0.as_bytes(), path.as_bytes())
+    }
+
+    fn matches_bytes(pattern: &[u8], path: &[u8]) -> bool {
+        match pattern {
+            [] => path.is_empty(),
+            [b'*', b'*', rest @ ..] => {
+                (0..=path.len()).any(|skip| Self::matches_bytes(rest, &path[skip..]))
+            }
+            [b'*', rest @ ..] => {
+                let segment_len = path.iter().position(|&c| c == b'/').unwrap_or(path.len());
+                (0..=segment_len).any(|skip| Self::matches_bytes(rest, &path[skip..]))
+            }
+            [b'?', rest @ ..] => match path {
+                [c, path_rest @ ..] if *c != b'/' => Self::matches_bytes(rest, path_rest),
+                _ => false,
+            },
+            [c, rest @ ..] => match path {
+                [p, path_rest @ ..] if p == c => Self::matches_bytes(rest, path_rest),
+                _ => false,
+            },
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum PathMatcher {
+    Prefix(String),
+    Glob(Glob),
+}
+
+impl PathMatcher {
+    pub fn matches(&self, path: &str) -> bool {
+        match self {
+            Self::Prefix(prefix) => path.starts_with(prefix.as_str()),
+            Self::Glob(glob) => glob.matches(path),
+        }
+    }
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct RouteConfig {
+    #[serde(default)]
+    prefix: Option<String>,
+    #[serde(default)]
+    glob: Option<String>,
+    continents: HashMap<String, Vec<String>>,
+}
+
+impl RouteConfig {
+    fn matcher(&self) -> Result<PathMatcher, RouteConfigError> {
+        match (&self.prefix, &self.glob) {
+            (Some(prefix), None) => Ok(PathMatcher::Prefix(prefix.clone())),
+            (None, Some(glob)) => Ok(PathMatcher::Glob(Glob::new(glob.clone()))),
+            _ => Err(RouteConfigError::PrefixOrGlob),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Route {
+    matcher: PathMatcher,
+    continent_map: ContinentMap,
+}
+
+/// Continent maps selected by request path, the first matching route wins
+#[derive(Debug, Clone)]
+pub struct Routes {
+    routes: Vec<Route>,
+    default: ContinentMap,
+}
+
+impl Routes {
+    pub fn from_config(
+        mirrors: &HashMap<String, Mirror>,
+        continents: &HashMap<String, Vec<String>>,
+        routes: &[RouteConfig],
+    ) -> Result<Self, RoutesConfigError> {
+        let default = ContinentMap::from_mirrors_and_continents(mirrors, continents)?;
+        let routes = routes
+            .iter()
+            .enumerate()
+            .map(|(index, route)| {
+                let map_err = |error| RoutesConfigError::Route { index, error };
+                Ok(Route {
+                    matcher: route.matcher().map_err(map_err)?,
+                    continent_map: ContinentMap::from_mirrors_and_continents(
+                        mirrors,
+                        &route.continents,
+                    )
+                    .map_err(|error| map_err(error.into()))?,
+                })
+            })
+            .collect::<Result<_, RoutesConfigError>>()?;
+        Ok(Self { routes, default })
+    }
+
+    pub fn get(&self, path: &str) -> &ContinentMap {
+        self.routes
+            .iter()
+            .find(|route| route.matcher.matches(path))
+            .map_or(&self.default, |route| &route.continent_map)
+    }
+
+    pub fn all_mirrors(&self) -> &[Mirror] {
+        self.default.all_mirrors()
+    }
+}
+
+#[derive(Error, Debug, PartialEq, Eq)]
+pub enum RouteConfigError {
+    #[error(r#"exactly one of "prefix" and "glob" must be specified"#)]
+    PrefixOrGlob,
+    #[error(transparent)]
+    ContinentMap(#[from] ContinentMapConfigError),
+}
+
+#[derive(Error, Debug, PartialEq, Eq)]
+pub enum RoutesConfigError {
+    #[error(transparent)]
+    ContinentMap(#[from] ContinentMapConfigError),
+    #[error("routes[{index}]: {error}")]
+    Route {
+        index: usize,
+        error: RouteConfigError,
+    },
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::geo::Continent;
+
+    #[test]
+    fn glob() {
+        for (pattern, path, expected) in [
+            ("/ztf/*", "/ztf/file", true),
+            ("/ztf/*", "/ztf/dir/file", false),
+            ("/ztf/**", "/ztf/dir/file", true),
+            ("/ztf/**", "/ztf/", true),
+            ("/**/*.fits", "/ztf/dir/file.fits", true),
+            ("/**/*.fits", "/ztf/dir/file.fits.gz", false),
+            ("/*/data/*", "/ztf/data/file", true),
+            ("/*/data/*", "/ztf/dr1/data/file", false),
+            ("/file?.txt", "/file1.txt", true),
+            ("/file?.txt", "/file/.txt", false),
+            ("/file?.txt", "/file.txt", false),
+            ("/exact", "/exact", true),
+            ("/exact", "/exact/", false),
+        ] {
+            assert_eq!(
+                Glob::new(pattern).matches(path),
+                expected,
+                "{pattern} {path}"
+            );
+        }
+    }
+
+    #[derive(Debug, Deserialize)]
+    struct RoutesConfig {
+        #[serde(deserialize_with = "crate::mirror::deserialize_mirrors")]
+        mirrors: HashMap<String, Mirror>,
+        continents: HashMap<String, Vec<String>>,
+        routes: Vec<RouteConfig>,
+    }
+
+    fn routes_from_str(s: &str) -> Result<Routes, RoutesConfigError> {
+        let config: RoutesConfig = toml::from_str(s).unwrap();
+        Routes::from_config(&config.mirrors, &config.continents, &config.routes)
+    }
+
+    #[test]
+    fn select_route() {
+        let routes = routes_from_str(
+            r#"
+            [mirrors]
+            a = { upstream = "http://a.example.com", healthcheck = "http://a.example.com/ping" }
+            b = { upstream = "http://b.example.com", healthcheck = "http://b.example.com/ping" }
+            c = { upstream = "http://c.example.com", healthcheck = "http://c.example.com/ping" }
+
+            [continents]
+            default = ["a", "b", "c"]
+
+            [[routes]]
+            prefix = "/sdss/"
+            continents = { default = ["b", "c"] }
+
+            [[routes]]
+            glob = "/**/*.fits"
+            continents = { default = ["c"] }
+            "#,
+        )
+        .unwrap();
+        let upstream = |path| {
+            routes.get(path).get(Continent::Default)[0]
+                .upstream
+                .to_string()
+        };
+        assert_eq!(upstream("/ztf/file"), "http://a.example.com/");
+        assert_eq!(upstream("/sdss/file.fits"), "http://b.example.com/");
+        assert_eq!(upstream("/ztf/file.fits"), "http://c.example.com/");
+        assert_eq!(routes.all_mirrors().len(), 3);
+    }
+
+    #[test]
+    fn route_without_matcher() {
+        let result = routes_from_str(
+            r#"
+            [mirrors]
+            a = { upstream = "http://a.example.com", healthcheck = "http://a.example.com/ping" }
+
+            [continents]
+            default = ["a"]
+
+            [[routes]]
+            continents = { default = ["a"] }
+            "#,
+        );
+        assert_eq!(
+            result.unwrap_err(),
+            RoutesConfigError::Route {
+                index: 0,
+                error: RouteConfigError::PrefixOrGlob
+            }
+        );
+    }
+
+    #[test]
+    fn route_with_unknown_mirror() {
+        let result = routes_from_str(
+            r#"
+            [mirrors]
+            a = { upstream = "http://a.example.com", healthcheck = "http://a.example.com/ping" }
+
+            [continents]
+            default = ["a"]
+
+            [[routes]]
+            prefix = "/sdss/"
+            continents = { default = ["b"] }
+            "#,
+        );
+        assert!(matches!(
+            result.unwrap_err(),
+            RoutesConfigError::Route {
+                index: 0,
+                error: RouteConfigError::ContinentMap(
+                    ContinentMapConfigError::MirrorUnknown { .. }
+                )
+            }
+        ));
+    }
+}
@@ -0,0 +1,344 @@
+use crate::config::TlsConfig;
+use crate::ip_network::{any_contains, IpNetwork};
+use crate::proxy_protocol;
+use crate::service::{ConnectionInfo, Geo302Service};
+#[cfg(feature = "tls")]
+use crate::tls::TlsError;
+
+use hyper::server::conn::Http;
+use hyper::service::service_fn;
+use hyper::{Body, Request};
+use serde::Deserialize;
+use std::convert::Infallible;
+use std::fmt;
+use std::net::{IpAddr, SocketAddr};
+#[cfg(unix)]
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use thiserror::Error;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::TcpListener;
+#[cfg(unix)]
+use tokio::net::UnixListener;
+#[cfg(feature = "tls")]
+use tokio_rustls::TlsAcceptor;
+
+#[cfg(feature = "tls")]
+const TLS_HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+const PROXY_HEADER_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Error, Debug)]
+pub enum ListenerError {
+    #[error(r#"Cannot listen on "{address}": {error}"#)]
+    Bind {
+        error: std::io::Error,
+        address: ListenAddress,
+    },
+    #[cfg(feature = "tls")]
+    #[error(transparent)]
+    Tls(#[from] TlsError),
+}
+
+#[derive(Error, Debug)]
+#[error(r#"Invalid listen address "{0}", must be "IP:PORT" or "unix:PATH""#)]
+pub struct InvalidListenAddress(String);
+
+/// Either TCP socket address or Unix domain socket path prefixed by "unix:"
+#[derive(Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(try_from = "String")]
+pub enum ListenAddress {
+    Tcp(SocketAddr),
+    #[cfg(unix)]
+    Unix(PathBuf),
+}
+
+impl TryFrom<String> for ListenAddress {
+    type Error = InvalidListenAddress;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        #[cfg(unix)]
+        if let Some(path) = s.strip_prefix("unix:") {
+            return if path.is_empty() {
+                Err(InvalidListenAddress(s))
+            } else {
+                Ok(Self::Unix(path.into()))
+            };
+        }
+        s.parse()
+            .map(Self::Tcp)
+            .map_err(|_| InvalidListenAddress(s))
+    }
+}
+
+impl From<SocketAddr> for ListenAddress {
+    fn from(address: SocketAddr) -> Self {
+        Self::Tcp(address)
+    }
+}
+
+impl fmt::Display for ListenAddress {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Tcp(address) => write!(f, "{address}"),
+            #[cfg(unix)]
+            Self::Unix(path) => write!(f, "unix:{}", path.display()),
+        }
+    }
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct ListenerConfig {
+    pub address: ListenAddress,
+    #[serde(default)]
+    pub tls: Option<TlsConfig>,
+    /// File permissions of the Unix domain socket, e.g. 0o660
+    #[serde(default)]
+    pub mode: Option<u32>,
+    #[serde(default)]
+    pub proxy_protocol: Option<ProxyProtocolConfig>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct ProxyProtocolConfig {
+    /// Peers allowed to send PROXY protocol header, connections from other peers are served as if
+    /// PROXY protocol is disabled. Unix domain socket peers are always trusted.
+    trusted: Vec<IpNetwork>,
+}
+
+impl ProxyProtocolConfig {
+    fn is_trusted(&self, socket_remote_ip: Option<IpAddr>) -> bool {
+        match socket_remote_ip {
+            Some(ip) => any_contains(&self.trusted, ip),
+            None => true,
+        }
+    }
+}
+
+impl ListenerConfig {
+    pub async fn bind(self) -> Result<Listener, ListenerError> {
+        let Self {
+            address,
+            #[allow(unused_variables)]
+            tls,
+            #[allow(unused_variables)]
+            mode,
+            proxy_protocol,
+        } = self;
+        let map_err = |error| ListenerError::Bind {
+            error,
+            address: address.clone(),
+        };
+        let socket = match &address {
+            ListenAddress::Tcp(socket_address) => {
+                ListenerSocket::Tcp(TcpListener::bind(socket_address).await.map_err(map_err)?)
+            }
+            #[cfg(unix)]
+            ListenAddress::Unix(path) => {
+                use std::os::unix::fs::{FileTypeExt, PermissionsExt};
+
+                // Remove a socket left by a previous run, bind would fail otherwise
+                if let Ok(metadata) = std::fs::symlink_metadata(path) {
+                    if metadata.file_type().is_socket() {
+                        std::fs::remove_file(path).map_err(map_err)?;
+                    }
+                }
+                let listener = UnixListener::bind(path).map_err(map_err)?;
+                if let Some(mode) = mode {
+                    std::fs::set_permissions(path, std::fs::Permissions::from_mode(mode))
+                        .map_err(map_err)?;
+                }
+                ListenerSocket::Unix(listener)
+            }
+        };
+        Ok(Listener {
+            socket,
+            #[cfg(feature = "tls")]
+            tls: tls.map(TlsConfig::acceptor).transpose()?,
+            proxy_protocol: proxy_protocol.map(Arc::new),
+            address,
+        })
+    }
+}
+
+enum ListenerSocket {
+    Tcp(TcpListener),
+    #[cfg(unix)]
+    Unix(UnixListener),
+}
+
+pub struct Listener {
+    socket: ListenerSocket,
+    #[cfg(feature = "tls")]
+    tls: Option<TlsAcceptor>,
+    proxy_protocol: Option<Arc<ProxyProtocolConfig>>,
+    address: ListenAddress,
+}
+
+impl Listener {
+    pub fn address(&self) -> &ListenAddress {
+        &self.address
+    }
+
+    /// Accept connections forever
+    pub async fn serve(self, geo302_service: Arc<Geo302Service>) -> std::io::Error {
+        log::info!("Listening on {}", self.address);
+        loop {
+            let result = match &self.socket {
+                ListenerSocket::Tcp(listener) => {
+                    listener.accept().await.map(|(stream, address)| {
+                        self.spawn_connection(stream, Some(address.ip()), geo302_service.clone())
+                    })
+                }
+                #[cfg(unix)]
+                ListenerSocket::Unix(listener) => {
+                    listener.accept().await.map(|(stream, _address)| {
+                        self.spawn_connection(stream, None, geo302_service.clone())
+                    })
+                }
+            };
+            match result {
+                Ok(()) => {}
+                Err(e) if is_connection_error(&e) => {}
+                Err(e) => {
+                    log::error!("Error accepting connection on {}: {e}", self.address);
+                    // Most probably we are out of file descriptors, wait and retry
+                    tokio::time::sleep(Duration::from_secs(1)).await;
+                }
+            }
+        }
+    }
+
+    fn spawn_connection<IO>(
+        &self,
+        mut stream: IO,
+        mut socket_remote_ip: Option<IpAddr>,
+        geo302_service: Arc<Geo302Service>,
+    ) where
+        IO: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+    {
+        #[cfg(feature = "tls")]
+        let tls = self.tls.clone();
+        let proxy_protocol = self.proxy_protocol.clone();
+        tokio::spawn(async move {
+            if let Some(proxy_protocol) = proxy_protocol {
+                if proxy_protocol.is_trusted(socket_remote_ip) {
+                    match tokio::time::timeout(
+                        PROXY_HEADER_TIMEOUT,
+                        proxy_protocol::read_header(&mut stream),
+                    )
+                    .await
+                    {
+                        Ok(Ok(Some(address))) => socket_remote_ip = Some(address.ip()),
+                        Ok(Ok(None)) => {}
+                        Ok(Err(e)) => {
+                            log::debug!("PROXY header from {socket_remote_ip:?} is invalid: {e}");
+                            return;
+                        }
+                        Err(_) => {
+                            log::debug!("PROXY header from {socket_remote_ip:?} timed out");
+                            return;
+                        }
+                    }
+                }
+            }
+            #[cfg(feature = "tls")]
+            if let Some(acceptor) = tls {
+                let stream = match tokio::time::timeout(
+                    TLS_HANDSHAKE_TIMEOUT,
+                    acceptor.accept(stream),
+                )
+                .await
+                {
+                    Ok(Ok(stream)) => stream,
+                    Ok(Err(e)) => {
+                        log::debug!("TLS handshake with {socket_remote_ip:?} failed: {e}");
+                        return;
+                    }
+                    Err(_) => {
+                        log::debug!("TLS handshake with {socket_remote_ip:?} timed out");
+                        return;
+                    }
+                };
+                let connection = ConnectionInfo {
+                    remote_ip: socket_remote_ip,
+                    server_name: stream.get_ref().1.server_name().map(ToOwned::to_owned),
+                };
+                serve_connection(stream, connection, geo302_service).await;
+                return;
+            }
+            let connection = ConnectionInfo {
+                remote_ip: socket_remote_ip,
+                server_name: None,
+            };
+            serve_connection(stream, connection, geo302_service).await;
+        });
+    }
+}
+
+fn is_connection_error(e: &std::io::Error) -> bool {
+    matches!(
+        e.kind(),
+        std::io::ErrorKind::ConnectionRefused
+            | std::io::ErrorKind::ConnectionAborted
+            | std::io::ErrorKind::ConnectionReset
+    )
+}
+
+async fn serve_connection<IO>(
+    io: IO,
+    connection: ConnectionInfo,
+    geo302_service: Arc<Geo302Service>,
+) where
+    IO: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    let socket_remote_ip = connection.remote_ip;
+    let connection = Arc::new(connection);
+    let service = service_fn(move |request: Request<Body>| {
+        let geo302_service = geo302_service.clone();
+        let connection = connection.clone();
+        async move {
+            let start = Instant::now();
+            let response = geo302_service.response(&connection, &request);
+            geo302_service.log_access(&connection, &request, &response, start.elapsed());
+            Ok::<_, Infallible>(response)
+        }
+    });
+    if let Err(e) = Http::new()
+        .http1_only(true)
+        .serve_connection(io, service)
+        .await
+    {
+        log::debug!("Error serving connection from {socket_remote_ip:?}: {e}");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_tcp_address() {
+        let address: ListenAddress = "[::]:8080".to_owned().try_into().unwrap();
+        assert_eq!(address, ListenAddress::Tcp("[::]:8080".parse().unwrap()));
+        assert_eq!(address.to_string(), "[::]:8080");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn parse_unix_address() {
+        let address: ListenAddress = "unix:/run/geo302.sock".to_owned().try_into().unwrap();
+        assert_eq!(address, ListenAddress::Unix("/run/geo302.sock".into()));
+        assert_eq!(address.to_string(), "unix:/run/geo302.sock");
+    }
+
+    #[test]
+    fn parse_invalid_address() {
+        for s in ["localhost", "127.0.0.1", "unix:"] {
+            let result: Result<ListenAddress, _> = s.to_owned().try_into();
+            assert!(result.is_err(), "{s}");
+        }
+    }
+}
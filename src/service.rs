@@ -1,32 +1,58 @@
+use crate::access_log::{AccessInfo, AccessLog, AccessLogConfigError, AccessRecord};
 use crate::canonical_ip::CanonicalIpAddr;
 use crate::config::Config;
-use crate::geo::{Geo, GeoError, GeoTrait};
-use crate::header_tools::client_ip;
+use crate::fallback::{Fallback, FallbackConfigError};
+use crate::geo::{Continent, Geo, GeoError, GeoTrait};
+use crate::header_tools::{client_ip, client_ip_trusted};
 use crate::healthcheck::HealthCheck;
-use crate::mirror::{ContinentMap, ContinentMapConfigError, Mirror};
-use crate::uri_tools::compose_uri;
+use crate::ip_network::{any_contains, IpNetwork};
+use crate::metalink::{
+    file_name, metalink_document, metalink_request, Duplicate, METALINK_CONTENT_TYPE,
+};
+use crate::mirror::{Mirror, MirrorVec};
+use crate::mirror_list::{mirror_list_request, MirrorList, MirrorListFormat};
+use crate::redirect::RedirectStatusMap;
+use crate::routes::{Routes, RoutesConfigError};
+use crate::spillover::{Spillover, SpilloverConfigError};
+use crate::virtual_host::{Site, VirtualHosts, VirtualHostsConfigError};
 
-use hyper::{header::HeaderMap, Body, Request, Response, StatusCode, Uri};
+use hyper::{header, header::HeaderMap, Body, Method, Request, Response, StatusCode, Uri};
 use std::net::IpAddr;
 use std::sync::atomic::Ordering;
+use std::time::Duration;
 use thiserror::Error;
 
 #[derive(Error, Debug)]
 pub enum ServiceError {
     #[error("No available mirrors")]
     MirrorsUnavailable,
-    #[error("Requested URI {0:?} is invalid")]
+    #[error("Requested URI is invalid")]
     InvalidUri(Uri),
-    #[error(r#"Internal server error: "{0:?}""#)]
+    #[error("Internal server error")]
     InternalServerError(#[from] hyper::http::Error),
 }
 
+/// Properties of the client connection
+#[derive(Debug, Clone, Default)]
+pub struct ConnectionInfo {
+    /// Socket peer IP, `None` for Unix domain sockets
+    pub remote_ip: Option<IpAddr>,
+    /// TLS SNI server name
+    pub server_name: Option<String>,
+}
+
 pub struct Geo302Service {
     ip_headers: Vec<String>,
     ip_headers_recursive: bool,
-    response_headers: HeaderMap,
+    trusted_proxies: Vec<IpNetwork>,
+    redirect_status: RedirectStatusMap,
+    mirror_list: bool,
+    metalink_suffix: bool,
+    access_log: Option<AccessLog>,
+    spillover: Spillover,
+    fallback: Fallback,
     geo: Geo,
-    continent_map: ContinentMap,
+    virtual_hosts: VirtualHosts,
     #[allow(dead_code)] // We need HealthCheck only for its side effects
     health_check: HealthCheck,
 }
@@ -36,18 +62,36 @@ impl Geo302Service {
         let Config {
             ip_headers,
             ip_headers_recursive,
+            trusted_proxies,
             response_headers,
+            redirect_status,
+            redirect_status_paths,
+            mirror_list,
+            metalink_suffix,
+            access_log: access_log_config,
+            spillover: spillover_config,
+            fallback: fallback_config,
             healthcheck: health_check_config,
             geoip: geo_config,
             mirrors: conf_mirrors,
             continents: conf_continents,
+            routes: conf_routes,
+            virtual_hosts: conf_virtual_hosts,
             ..
         } = config;
 
-        let continent_map =
-            ContinentMap::from_mirrors_and_continents(&conf_mirrors, &conf_continents)?;
+        let default_site = Site {
+            response_headers,
+            routes: Routes::from_config(&conf_mirrors, &conf_continents, &conf_routes)?,
+        };
+        let virtual_hosts =
+            VirtualHosts::from_config(default_site, &conf_mirrors, conf_virtual_hosts)?;
+
+        let access_log = access_log_config.map(|config| config.open()).transpose()?;
+        let spillover = Spillover::from_config(&spillover_config)?;
+        let fallback = Fallback::from_config(fallback_config, &conf_mirrors)?;
 
-        let health_check = health_check_config.start(continent_map.all_mirrors());
+        let health_check = health_check_config.start(virtual_hosts.all_mirrors());
 
         let geo = geo_config.load()?;
         geo.start_autoupdate();
@@ -55,85 +99,309 @@ impl Geo302Service {
         Ok(Self {
             ip_headers,
             ip_headers_recursive,
-            response_headers,
+            trusted_proxies,
+            redirect_status: RedirectStatusMap::new(redirect_status, redirect_status_paths),
+            mirror_list,
+            metalink_suffix,
+            access_log,
+            spillover,
+            fallback,
             geo,
-            continent_map,
+            virtual_hosts,
             health_check,
         })
     }
 }
 
 impl Geo302Service {
-    fn mirror(&self, remote_ip: IpAddr) -> Result<Mirror, ServiceError> {
-        let mirrors = match self.geo.try_lookup_continent(remote_ip).ok() {
-            Some(continent) => self.continent_map.get(continent),
-            None => self.continent_map.get_default(),
+    /// Available mirrors for the client location in priority order. If none of the continent's
+    /// mirrors is available, its spillover chain is walked until a continent with available
+    /// mirrors is found, the spillover is counted only if `record_spillover` is set, i.e. for
+    /// actual redirects
+    fn available_mirrors<'a>(
+        &self,
+        site: &'a Site,
+        continent: Option<Continent>,
+        path: &str,
+        record_spillover: bool,
+    ) -> Vec<&'a Mirror> {
+        let continent_map = site.routes.get(path);
+        let continent = continent.unwrap_or(Continent::Default);
+        let available = |mirrors: &'a MirrorVec| -> Vec<&'a Mirror> {
+            mirrors
+                .iter()
+                .filter(|mirror| mirror.available.load(Ordering::Acquire))
+                .collect()
         };
-        let mut it_mirrors = mirrors.iter();
-        loop {
-            match it_mirrors.next() {
-                Some(mirror) => {
-                    if mirror.available.load(Ordering::Acquire) {
-                        return Ok(mirror.clone());
-                    }
-                }
-                None => {
-                    return Err(ServiceError::MirrorsUnavailable);
+        let mirrors = available(continent_map.get(continent));
+        if !mirrors.is_empty() {
+            return mirrors;
+        }
+        // Continents without their own mirror list are skipped
+        for (index, spillover_continent) in self.spillover.chain(continent).enumerate() {
+            let mirrors = match continent_map.get_exact(spillover_continent) {
+                Some(mirrors) => available(mirrors),
+                None => continue,
+            };
+            if !mirrors.is_empty() {
+                if record_spillover {
+                    self.spillover.record(continent, index);
                 }
+                return mirrors;
             }
         }
+        vec![]
+    }
+
+    /// Client IP from headers with a fallback to the socket peer IP, which is `None` for Unix
+    /// domain sockets. `None` means that the client location is unknown and the default mirror
+    /// list should be used.
+    ///
+    /// If trusted proxies are specified, headers are used only when the socket peer is trusted,
+    /// Unix domain socket peers are always trusted.
+    fn remote_ip(&self, headers: &HeaderMap, socket_ip_addr: Option<IpAddr>) -> Option<IpAddr> {
+        if self.trusted_proxies.is_empty() {
+            return client_ip(headers, &self.ip_headers, self.ip_headers_recursive)
+                .or(socket_ip_addr);
+        }
+        let is_peer_trusted = match socket_ip_addr {
+            Some(ip) => any_contains(&self.trusted_proxies, ip),
+            None => true,
+        };
+        if !is_peer_trusted {
+            return socket_ip_addr;
+        }
+        client_ip_trusted(headers, &self.ip_headers, &self.trusted_proxies).or(socket_ip_addr)
+    }
+
+    /// Methods we answer to OPTIONS with, all of them are redirected
+    const ALLOWED_METHODS: &'static str = "OPTIONS, GET, HEAD, POST, PUT, PATCH, DELETE";
+
+    /// Select site by the request URI authority, "Host" header or TLS SNI, in this order
+    fn site(&self, connection: &ConnectionInfo, request: &Request<Body>) -> &Site {
+        let host = request
+            .uri()
+            .authority()
+            .map(|authority| authority.as_str())
+            .or_else(|| {
+                request
+                    .headers()
+                    .get(header::HOST)
+                    .and_then(|value| value.to_str().ok())
+            })
+            .or(connection.server_name.as_deref());
+        self.virtual_hosts.get(host)
+    }
+
+    fn response_builder(&self, site: &Site, status: StatusCode) -> hyper::http::response::Builder {
+        let mut response_builder = Response::builder().status(status);
+        let headers = response_builder.headers_mut().unwrap();
+        for (name, value) in &site.response_headers {
+            headers.insert(name, value.clone());
+        }
+        response_builder
     }
 
-    fn remote_ip(&self, headers: &HeaderMap, socket_ip_addr: IpAddr) -> IpAddr {
-        client_ip(headers, &self.ip_headers, self.ip_headers_recursive).unwrap_or(socket_ip_addr)
+    /// OPTIONS requests, including CORS preflight, are answered directly and not redirected
+    fn options_response(&self, site: &Site) -> Result<Response<Body>, ServiceError> {
+        let response = self
+            .response_builder(site, StatusCode::NO_CONTENT)
+            .header(header::ALLOW, Self::ALLOWED_METHODS)
+            .body(Body::empty())?;
+        Ok(response)
     }
 
-    pub fn response(
+    /// Healthy mirrors with their URLs for the file path, the same order as for redirects
+    fn mirror_list_response(
         &self,
-        socket_ip_addr: IpAddr,
+        site: &Site,
+        continent: Option<Continent>,
+        path: &str,
+        format: MirrorListFormat,
+    ) -> Result<Response<Body>, ServiceError> {
+        let mut mirror_list = MirrorList::new(continent, path);
+        for mirror in self.available_mirrors(site, continent, path, false) {
+            mirror_list.push(mirror, mirror.location(path, None)?.to_string());
+        }
+        let response = self
+            .response_builder(site, StatusCode::OK)
+            .header(header::CONTENT_TYPE, format.content_type())
+            .header(header::CACHE_CONTROL, "no-store")
+            .extension(AccessInfo {
+                continent,
+                mirror: None,
+            })
+            .body(mirror_list.render(format).into())?;
+        Ok(response)
+    }
+
+    /// Error response, its body never includes internal details, which are logged instead
+    fn error_response(&self, error: ServiceError) -> Response<Body> {
+        let status = match &error {
+            ServiceError::MirrorsUnavailable => {
+                return self.fallback.unavailable_response(error.to_string())
+            }
+            ServiceError::InvalidUri(_) => StatusCode::BAD_REQUEST,
+            ServiceError::InternalServerError(e) => {
+                log::error!("Internal server error: {e}");
+                StatusCode::INTERNAL_SERVER_ERROR
+            }
+        };
+        Response::builder()
+            .status(status)
+            .header(header::CONTENT_TYPE, "text/plain; charset=utf-8")
+            .body(error.to_string().into())
+            .unwrap()
+    }
+
+    /// Response to the request, error responses get [AccessInfo] known by the time of the error
+    pub fn response(&self, connection: &ConnectionInfo, request: &Request<Body>) -> Response<Body> {
+        let mut access_info = AccessInfo::default();
+        self.try_response(connection, request, &mut access_info)
+            .unwrap_or_else(|error| {
+                let mut response = self.error_response(error);
+                response.extensions_mut().insert(access_info);
+                response
+            })
+    }
+
+    /// `access_info` is filled as soon as its fields are known
+    fn try_response(
+        &self,
+        connection: &ConnectionInfo,
         request: &Request<Body>,
+        access_info: &mut AccessInfo,
     ) -> Result<Response<Body>, ServiceError> {
+        let site = self.site(connection, request);
+        if request.method() == Method::OPTIONS {
+            return self.options_response(site);
+        }
         let remote_ip = self
-            .remote_ip(request.headers(), socket_ip_addr)
-            .to_canonical_ip();
-        let mirror = self.mirror(remote_ip)?;
+            .remote_ip(request.headers(), connection.remote_ip)
+            .map(|ip| ip.to_canonical_ip());
         let request_path = request
             .uri()
             .path_and_query()
             .ok_or_else(|| ServiceError::InvalidUri(request.uri().clone()))?;
-        let uri = compose_uri(&mirror.upstream, request_path.as_str())?;
-        let response = {
-            let mut response_builder = Response::builder()
-                .status(StatusCode::FOUND)
-                .header("Location", uri.to_string());
-            {
-                let headers = response_builder.headers_mut().unwrap();
-                for (name, value) in &self.response_headers {
-                    headers.insert(name, value.clone());
-                }
+        let path = request_path.path();
+        let continent = remote_ip.and_then(|ip| self.geo.try_lookup_continent(ip).ok());
+        access_info.continent = continent;
+
+        if self.mirror_list {
+            if let Some(file_path) = mirror_list_request(path) {
+                let format =
+                    MirrorListFormat::from_request(request_path.query(), request.headers());
+                return self.mirror_list_response(site, continent, file_path, format);
             }
-            response_builder.body(Body::empty())?
+        }
+
+        let metalink_name = metalink_request(request.headers(), path, self.metalink_suffix)
+            .and_then(|path| Some((path, file_name(path)?)));
+        let metalink_path = metalink_name.as_ref().map(|(path, _)| *path);
+        let mut mirrors =
+            self.available_mirrors(site, continent, metalink_path.unwrap_or(path), true);
+        if mirrors.is_empty() {
+            mirrors.push(
+                self.fallback
+                    .origin
+                    .as_ref()
+                    .ok_or(ServiceError::MirrorsUnavailable)?,
+            );
+        }
+        access_info.mirror = Some(mirrors[0].name.clone());
+        let file_path = metalink_path.unwrap_or(path);
+        let primary = Duplicate {
+            uri: mirrors[0].location(file_path, request_path.query())?,
+            priority: 1,
+            geo: mirrors[0].geo.as_deref(),
         };
+        // Alternative locations are best-effort, the primary one is enough for a redirect
+        let secondaries = mirrors[1..].iter().filter_map(|mirror| {
+            match mirror.location(file_path, request_path.query()) {
+                Ok(uri) => Some((uri, mirror.geo.as_deref())),
+                Err(e) => {
+                    log::warn!("Cannot build location of mirror {}: {e}", mirror.name);
+                    None
+                }
+            }
+        });
+        let mut duplicates = std::iter::once(primary)
+            .chain(
+                secondaries
+                    .enumerate()
+                    .map(|(index, (uri, geo))| Duplicate {
+                        uri,
+                        priority: index + 2,
+                        geo,
+                    }),
+            )
+            .collect::<Vec<_>>();
+        if let Some((_, file_name)) = metalink_name {
+            let response = self
+                .response_builder(site, StatusCode::OK)
+                .header(header::CONTENT_TYPE, METALINK_CONTENT_TYPE)
+                .extension(access_info.clone())
+                .body(metalink_document(&file_name, &duplicates).into())?;
+            return Ok(response);
+        }
+
+        let location = duplicates.remove(0).uri;
+        // Body is empty for all methods, so HEAD response is the same as GET one
+        let mut response_builder = self
+            .response_builder(site, self.redirect_status.get(path))
+            .header(header::LOCATION, location.to_string())
+            .extension(access_info.clone());
+        // RFC 6249 alternative locations of the same file
+        for (index, duplicate) in duplicates.iter_mut().enumerate() {
+            duplicate.priority = index + 1;
+            response_builder = response_builder.header(header::LINK, duplicate.link_header());
+        }
+        let response = response_builder.body(Body::empty())?;
         Ok(response)
     }
-}
 
-pub fn make_error_response(error: ServiceError) -> Response<Body> {
-    let status = match error {
-        ServiceError::MirrorsUnavailable => StatusCode::SERVICE_UNAVAILABLE,
-        ServiceError::InvalidUri(_) => StatusCode::BAD_REQUEST,
-        ServiceError::InternalServerError(_) => StatusCode::INTERNAL_SERVER_ERROR,
-    };
-    Response::builder()
-        .status(status)
-        .body(format!("{error:?}").into())
-        .unwrap()
+    /// Reopen access log file if configured
+    pub fn reopen_access_log(&self) {
+        if let Some(access_log) = &self.access_log {
+            access_log.reopen();
+        }
+    }
+
+    /// Write access log record, or log it with the diagnostic logger if access log is not
+    /// configured
+    pub fn log_access(
+        &self,
+        connection: &ConnectionInfo,
+        request: &Request<Body>,
+        response: &Response<Body>,
+        latency: Duration,
+    ) {
+        match &self.access_log {
+            Some(access_log) => {
+                let client_ip = self
+                    .remote_ip(request.headers(), connection.remote_ip)
+                    .map(|ip| ip.to_canonical_ip());
+                access_log.write(&AccessRecord::new(
+                    request,
+                    response,
+                    client_ip,
+                    connection.remote_ip,
+                    latency,
+                ));
+            }
+            None => log_response(connection.remote_ip, request, response),
+        }
+    }
 }
 
-pub fn log_response(socket_ip_addr: IpAddr, request: &Request<Body>, response: &Response<Body>) {
+fn log_response(
+    socket_ip_addr: Option<IpAddr>,
+    request: &Request<Body>,
+    response: &Response<Body>,
+) {
     log::info!(
         "{} {} {} {} {}",
-        socket_ip_addr,
+        socket_ip_addr.map_or_else(|| "-".to_owned(), |ip| ip.to_string()),
         request.method(),
         request.uri(),
         response.status(),
@@ -148,7 +416,15 @@ pub fn log_response(socket_ip_addr: IpAddr, request: &Request<Body>, response: &
 #[derive(Debug, Error)]
 pub enum InvalidConfigError {
     #[error(transparent)]
-    ContinentMapConfigError(#[from] ContinentMapConfigError),
+    RoutesConfigError(#[from] RoutesConfigError),
+    #[error(transparent)]
+    VirtualHostsConfigError(#[from] VirtualHostsConfigError),
+    #[error(transparent)]
+    AccessLogConfigError(#[from] AccessLogConfigError),
+    #[error(transparent)]
+    SpilloverConfigError(#[from] SpilloverConfigError),
+    #[error(transparent)]
+    FallbackConfigError(#[from] FallbackConfigError),
     #[error(transparent)]
     GeoError(#[from] GeoError),
 }
@@ -0,0 +1,146 @@
+use crate::geo::Continent;
+
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use thiserror::Error;
+
+#[derive(Debug)]
+struct SpilloverStep {
+    continent: Continent,
+    /// Number of times this step was used
+    count: AtomicU64,
+}
+
+/// Per-continent chains of other continents to try when no mirror of the client's continent is
+/// available
+#[derive(Debug, Default)]
+pub struct Spillover {
+    chains: HashMap<Continent, Vec<SpilloverStep>>,
+}
+
+impl Spillover {
+    pub fn from_config(
+        config: &HashMap<String, Vec<String>>,
+    ) -> Result<Self, SpilloverConfigError> {
+        let parse = |s: &String| {
+            Continent::try_from(s.as_str())
+                .map_err(|_| SpilloverConfigError::ContinentUnknown(s.to_owned()))
+        };
+        let chains = config
+            .iter()
+            .map(|(from, chain)| {
+                let from = parse(from)?;
+                let chain = chain
+                    .iter()
+                    .map(|to| {
+                        let continent = parse(to)?;
+                        if continent == from {
+                            return Err(SpilloverConfigError::SelfReference(from));
+                        }
+                        Ok(SpilloverStep {
+                            continent,
+                            count: AtomicU64::new(0),
+                        })
+                    })
+                    .collect::<Result<_, _>>()?;
+                Ok((from, chain))
+            })
+            .collect::<Result<_, SpilloverConfigError>>()?;
+        Ok(Self { chains })
+    }
+
+    /// Continents to try in order after `from`
+    pub fn chain(&self, from: Continent) -> impl Iterator<Item = Continent> + '_ {
+        self.chains
+            .get(&from)
+            .into_iter()
+            .flatten()
+            .map(|step| step.continent)
+    }
+
+    /// Count and log spillover from the continent to the `index`-th continent of its chain. Only
+    /// the first spillover of each pair is logged at info level, so outages don't flood the log
+    pub fn record(&self, from: Continent, index: usize) {
+        let step = &self.chains[&from][index];
+        let count = step.count.fetch_add(1, Ordering::Relaxed) + 1;
+        let level = if count == 1 {
+            log::Level::Info
+        } else {
+            log::Level::Debug
+        };
+        log::log!(
+            level,
+            "No available mirrors for {from:?}, spilled over to {:?} ({count} times in total)",
+            step.continent
+        );
+    }
+
+    /// Number of spillovers from one continent to another
+    #[cfg(test)]
+    fn count(&self, from: Continent, to: Continent) -> u64 {
+        self.chains
+            .get(&from)
+            .into_iter()
+            .flatten()
+            .find(|step| step.continent == to)
+            .map_or(0, |step| step.count.load(Ordering::Relaxed))
+    }
+}
+
+#[derive(Error, Debug, PartialEq, Eq)]
+pub enum SpilloverConfigError {
+    #[error(r#"spillover continent {0} is unknown"#)]
+    ContinentUnknown(String),
+    #[error(r#"spillover chain of {0:?} includes itself"#)]
+    SelfReference(Continent),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn spillover_from_str(s: &str) -> Result<Spillover, SpilloverConfigError> {
+        let config: HashMap<String, Vec<String>> = toml::from_str(s).unwrap();
+        Spillover::from_config(&config)
+    }
+
+    #[test]
+    fn chain_and_count() {
+        let spillover = spillover_from_str(
+            r#"
+            SouthAmerica = ["NorthAmerica", "default"]
+            Europe = []
+            "#,
+        )
+        .unwrap();
+        assert_eq!(
+            spillover.chain(Continent::SouthAmerica).collect::<Vec<_>>(),
+            [Continent::NorthAmerica, Continent::Default]
+        );
+        assert_eq!(spillover.chain(Continent::Europe).count(), 0);
+        assert_eq!(spillover.chain(Continent::Asia).count(), 0);
+
+        spillover.record(Continent::SouthAmerica, 1);
+        spillover.record(Continent::SouthAmerica, 1);
+        assert_eq!(
+            spillover.count(Continent::SouthAmerica, Continent::Default),
+            2
+        );
+        assert_eq!(
+            spillover.count(Continent::SouthAmerica, Continent::NorthAmerica),
+            0
+        );
+    }
+
+    #[test]
+    fn invalid() {
+        assert_eq!(
+            spillover_from_str(r#"Atlantis = ["default"]"#).unwrap_err(),
+            SpilloverConfigError::ContinentUnknown("Atlantis".to_owned())
+        );
+        assert_eq!(
+            spillover_from_str(r#"Europe = ["Asia", "Europe"]"#).unwrap_err(),
+            SpilloverConfigError::SelfReference(Continent::Europe)
+        );
+    }
+}
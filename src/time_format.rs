@@ -0,0 +1,68 @@
+use std::fmt::Write;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// UTC date and time: year, month, day, hour, minute, second, millisecond
+fn utc_date_time(time: SystemTime) -> (i64, u32, u32, u64, u64, u64, u32) {
+    let since_epoch = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+    let secs = since_epoch.as_secs();
+    let (hour, minute, second) = (secs / 3600 % 24, secs / 60 % 60, secs % 60);
+    // Convert days since epoch to civil date, see
+    // http://howardhinnant.github.io/date_algorithms.html#civil_from_days
+    let z = (secs / 86400) as i64 + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    (
+        year,
+        month,
+        day,
+        hour,
+        minute,
+        second,
+        since_epoch.subsec_millis(),
+    )
+}
+
+/// RFC 3339 UTC time with milliseconds, e.g. "2000-10-10T13:55:36.123Z"
+pub fn rfc3339(time: SystemTime) -> String {
+    let (year, month, day, hour, minute, second, millis) = utc_date_time(time);
+    format!("{year:04}-{month:02}-{day:02}T{hour:02}:{minute:02}:{second:02}.{millis:03}Z")
+}
+
+/// Common log format time, e.g. "10/Oct/2000:13:55:36 +0000"
+pub fn clf_time(time: SystemTime) -> String {
+    const MONTHS: [&str; 12] = [
+        "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+    ];
+    let (year, month, day, hour, minute, second, _) = utc_date_time(time);
+    let mut s = String::new();
+    write!(
+        s,
+        "{day:02}/{}/{year:04}:{hour:02}:{minute:02}:{second:02} +0000",
+        MONTHS[month as usize - 1]
+    )
+    .unwrap();
+    s
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn time_formats() {
+        assert_eq!(rfc3339(UNIX_EPOCH), "1970-01-01T00:00:00.000Z");
+        let time = UNIX_EPOCH + Duration::from_millis(971186136123);
+        assert_eq!(rfc3339(time), "2000-10-10T13:55:36.123Z");
+        assert_eq!(clf_time(time), "10/Oct/2000:13:55:36 +0000");
+        // Leap day
+        let time = UNIX_EPOCH + Duration::from_secs(1709164800);
+        assert_eq!(rfc3339(time), "2024-02-29T00:00:00.000Z");
+    }
+}
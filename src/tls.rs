@@ -0,0 +1,312 @@
+use crate::non_zero_duration::NonZeroDuration;
+
+use rustls::server::{ClientHello, ResolvesServerCert};
+use rustls::sign::CertifiedKey;
+use rustls::{Certificate, PrivateKey, ServerConfig};
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::io::BufReader;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, SystemTime};
+use thiserror::Error;
+use tokio_rustls::TlsAcceptor;
+
+#[derive(Error, Debug)]
+pub enum TlsError {
+    #[error(r#"Error reading file "{path}": {error}"#)]
+    IoError {
+        error: std::io::Error,
+        path: PathBuf,
+    },
+    #[error(r#"No certificates found in "{0}""#)]
+    NoCertificates(PathBuf),
+    #[error(r#"No private key found in "{0}""#)]
+    NoPrivateKey(PathBuf),
+    #[error(r#"Private key "{0}" is not supported"#)]
+    UnsupportedPrivateKey(PathBuf),
+    #[error("At least one certificate must be specified")]
+    NoCertificateConfigs,
+}
+
+/// A certificate-key pair, served for the given SNI server names
+#[derive(Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct TlsCertificateConfig {
+    cert: PathBuf,
+    key: PathBuf,
+    /// Names to match against SNI, "*.example.org" wildcards are supported. The first certificate
+    /// in the list is also used for clients which send no SNI or an unknown name.
+    #[serde(default)]
+    server_names: Vec<String>,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct TlsConfig {
+    certificates: Vec<TlsCertificateConfig>,
+    /// How often to check certificate files for modification
+    #[serde(default = "TlsConfig::default_reload_interval")]
+    reload_interval: NonZeroDuration,
+}
+
+impl TlsConfig {
+    fn default_reload_interval() -> NonZeroDuration {
+        NonZeroDuration::from_secs(60).unwrap()
+    }
+
+    /// Load certificates and build an acceptor which picks up certificate changes on SIGHUP or
+    /// file modification
+    pub fn acceptor(self) -> Result<TlsAcceptor, TlsError> {
+        let resolver = Arc::new(CertResolver::new(CertStore::load(&self.certificates)?));
+        let mut server_config = ServerConfig::builder()
+            .with_safe_defaults()
+            .with_no_client_auth()
+            .with_cert_resolver(resolver.clone());
+        server_config.alpn_protocols = vec![b"http/1.1".to_vec()];
+        self.start_reload(resolver);
+        Ok(TlsAcceptor::from(Arc::new(server_config)))
+    }
+
+    fn modified(&self) -> Vec<Option<SystemTime>> {
+        self.certificates
+            .iter()
+            .flat_map(|c| [&c.cert, &c.key])
+            .map(|path| std::fs::metadata(path).and_then(|m| m.modified()).ok())
+            .collect()
+    }
+
+    fn start_reload(self, resolver: Arc<CertResolver>) -> tokio::task::JoinHandle<()> {
+        let interval: Duration = self.reload_interval.clone().into();
+        tokio::spawn(async move {
+            #[cfg(unix)]
+            let mut sighup =
+                tokio::signal::unix::signal(tokio::signal::unix::SignalKind::hangup()).ok();
+            let mut watch = ModificationWatch::new(self.modified());
+            loop {
+                #[cfg(unix)]
+                let forced = match &mut sighup {
+                    Some(sighup) => tokio::select! {
+                        _ = sighup.recv() => true,
+                        _ = tokio::time::sleep(interval) => false,
+                    },
+                    None => {
+                        tokio::time::sleep(interval).await;
+                        false
+                    }
+                };
+                #[cfg(not(unix))]
+                let forced = {
+                    tokio::time::sleep(interval).await;
+                    false
+                };
+
+                if !watch.reload_due(self.modified(), forced) {
+                    continue;
+                }
+                match CertStore::load(&self.certificates) {
+                    Ok(store) => {
+                        resolver.replace(store);
+                        log::info!("TLS certificates reloaded");
+                    }
+                    Err(e) => log::error!("Error while reloading TLS certificates: {e}"),
+                }
+            }
+        })
+    }
+}
+
+/// Tracks modification times of certificate and key files. Files are usually replaced one after
+/// another and rustls doesn't check that a key matches its certificate, so an automatic reload is
+/// due only once the files have been unchanged for a whole interval
+struct ModificationWatch {
+    loaded: Vec<Option<SystemTime>>,
+    observed: Vec<Option<SystemTime>>,
+}
+
+impl ModificationWatch {
+    fn new(modified: Vec<Option<SystemTime>>) -> Self {
+        Self {
+            loaded: modified.clone(),
+            observed: modified,
+        }
+    }
+
+    /// `modified` are the current modification times, `forced` reloads them immediately
+    fn reload_due(&mut self, modified: Vec<Option<SystemTime>>, forced: bool) -> bool {
+        let stable = modified == self.observed;
+        self.observed = modified;
+        if forced || (stable && self.observed != self.loaded) {
+            self.loaded = self.observed.clone();
+            true
+        } else {
+            false
+        }
+    }
+}
+
+struct CertStore {
+    by_name: HashMap<String, Arc<CertifiedKey>>,
+    default: Arc<CertifiedKey>,
+}
+
+impl CertStore {
+    fn load(configs: &[TlsCertificateConfig]) -> Result<Self, TlsError> {
+        let mut by_name = HashMap::new();
+        let mut default = None;
+        for config in configs {
+            let certified_key = Arc::new(load_certified_key(&config.cert, &config.key)?);
+            for name in &config.server_names {
+                by_name
+                    .entry(name.to_ascii_lowercase())
+                    .or_insert_with(|| certified_key.clone());
+            }
+            default.get_or_insert(certified_key);
+        }
+        Ok(Self {
+            by_name,
+            default: default.ok_or(TlsError::NoCertificateConfigs)?,
+        })
+    }
+
+    fn get(&self, server_name: Option<&str>) -> Arc<CertifiedKey> {
+        server_name
+            .map(|name| name.to_ascii_lowercase())
+            .and_then(|name| {
+                self.by_name.get(&name).or_else(|| {
+                    let (_, parent) = name.split_once('.')?;
+                    self.by_name.get(&format!("*.{parent}"))
+                })
+            })
+            .unwrap_or(&self.default)
+            .clone()
+    }
+}
+
+struct CertResolver(RwLock<Arc<CertStore>>);
+
+impl CertResolver {
+    fn new(store: CertStore) -> Self {
+        Self(RwLock::new(Arc::new(store)))
+    }
+
+    fn replace(&self, store: CertStore) {
+        *self.0.write().unwrap() = Arc::new(store);
+    }
+}
+
+impl ResolvesServerCert for CertResolver {
+    fn resolve(&self, client_hello: ClientHello) -> Option<Arc<CertifiedKey>> {
+        let store = self.0.read().unwrap().clone();
+        Some(store.get(client_hello.server_name()))
+    }
+}
+
+fn open(path: &Path) -> Result<BufReader<std::fs::File>, TlsError> {
+    std::fs::File::open(path)
+        .map(BufReader::new)
+        .map_err(|error| TlsError::IoError {
+            error,
+            path: path.to_owned(),
+        })
+}
+
+fn load_certified_key(cert_path: &Path, key_path: &Path) -> Result<CertifiedKey, TlsError> {
+    let certs: Vec<_> = rustls_pemfile::certs(&mut open(cert_path)?)
+        .map_err(|error| TlsError::IoError {
+            error,
+            path: cert_path.to_owned(),
+        })?
+        .into_iter()
+        .map(Certificate)
+        .collect();
+    if certs.is_empty() {
+        return Err(TlsError::NoCertificates(cert_path.to_owned()));
+    }
+
+    let key = rustls_pemfile::read_all(&mut open(key_path)?)
+        .map_err(|error| TlsError::IoError {
+            error,
+            path: key_path.to_owned(),
+        })?
+        .into_iter()
+        .find_map(|item| match item {
+            rustls_pemfile::Item::RSAKey(key)
+            | rustls_pemfile::Item::PKCS8Key(key)
+            | rustls_pemfile::Item::ECKey(key) => Some(PrivateKey(key)),
+            _ => None,
+        })
+        .ok_or_else(|| TlsError::NoPrivateKey(key_path.to_owned()))?;
+    let signing_key = rustls::sign::any_supported_type(&key)
+        .map_err(|_| TlsError::UnsupportedPrivateKey(key_path.to_owned()))?;
+
+    Ok(CertifiedKey::new(certs, signing_key))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use rustls::sign::{Signer, SigningKey};
+    use rustls::{SignatureAlgorithm, SignatureScheme};
+
+    struct TestKey;
+
+    impl SigningKey for TestKey {
+        fn choose_scheme(&self, _offered: &[SignatureScheme]) -> Option<Box<dyn Signer>> {
+            None
+        }
+
+        fn algorithm(&self) -> SignatureAlgorithm {
+            SignatureAlgorithm::ECDSA
+        }
+    }
+
+    /// Certificate is identified by its content, which is the name here
+    fn certified_key(name: &str) -> Arc<CertifiedKey> {
+        Arc::new(CertifiedKey::new(
+            vec![Certificate(name.as_bytes().to_vec())],
+            Arc::new(TestKey),
+        ))
+    }
+
+    fn name(certified_key: Arc<CertifiedKey>) -> String {
+        String::from_utf8(certified_key.end_entity_cert().unwrap().0.clone()).unwrap()
+    }
+
+    #[test]
+    fn cert_store_get() {
+        let store = CertStore {
+            by_name: [
+                ("example.org", certified_key("exact")),
+                ("*.example.org", certified_key("wildcard")),
+            ]
+            .into_iter()
+            .map(|(name, key)| (name.to_owned(), key))
+            .collect(),
+            default: certified_key("default"),
+        };
+        assert_eq!(name(store.get(Some("example.org"))), "exact");
+        assert_eq!(name(store.get(Some("Example.ORG"))), "exact");
+        assert_eq!(name(store.get(Some("www.example.org"))), "wildcard");
+        assert_eq!(name(store.get(Some("WWW.Example.org"))), "wildcard");
+        // Wildcard matches a single label only
+        assert_eq!(name(store.get(Some("a.b.example.org"))), "default");
+        assert_eq!(name(store.get(Some("example.com"))), "default");
+        assert_eq!(name(store.get(None)), "default");
+    }
+
+    #[test]
+    fn modification_watch() {
+        let time = |secs| Some(SystemTime::UNIX_EPOCH + Duration::from_secs(secs));
+        let mut watch = ModificationWatch::new(vec![time(0), time(0)]);
+        assert!(!watch.reload_due(vec![time(0), time(0)], false));
+        // Certificate is replaced, the key is not yet
+        assert!(!watch.reload_due(vec![time(1), time(0)], false));
+        assert!(!watch.reload_due(vec![time(1), time(2)], false));
+        // Both are unchanged for an interval
+        assert!(watch.reload_due(vec![time(1), time(2)], false));
+        assert!(!watch.reload_due(vec![time(1), time(2)], false));
+        assert!(watch.reload_due(vec![time(1), time(2)], true));
+    }
+}
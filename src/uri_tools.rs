@@ -1,10 +1,143 @@
-use hyper::http::{Error, Uri};
-
-pub fn compose_uri(base_uri: &Uri, path: &str) -> Result<Uri, Error> {
-    let new_path = [base_uri.path(), path].concat();
-    Uri::builder()
-        .scheme(base_uri.scheme().unwrap().clone())
-        .authority(base_uri.authority().unwrap().clone())
-        .path_and_query(new_path)
-        .build()
+use hyper::http::uri::{InvalidUri, Uri};
+use hyper::http::Error;
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum AbsoluteUriError {
+    #[error(r#"invalid URI "{uri}": {error}"#)]
+    InvalidUri { uri: String, error: InvalidUri },
+    #[error(r#"URI "{0}" must be absolute, i.e. include scheme and host"#)]
+    NotAbsolute(String),
+}
+
+/// Parse URI and check that it has both scheme and authority
+pub fn parse_absolute_uri(s: &str) -> Result<Uri, AbsoluteUriError> {
+    let uri: Uri = s.try_into().map_err(|error| AbsoluteUriError::InvalidUri {
+        uri: s.to_owned(),
+        error,
+    })?;
+    if uri.scheme().is_none() || uri.authority().is_none() {
+        return Err(AbsoluteUriError::NotAbsolute(s.to_owned()));
+    }
+    Ok(uri)
+}
+
+/// Join base URI with the request path and query.
+///
+/// Exactly one slash is put between the base path and the request path, queries of the base URI
+/// and the request are merged with the base query first. Both path and query are used as is,
+/// so percent-encoding is preserved. Base URI is expected to be absolute, see
+/// [parse_absolute_uri].
+pub fn compose_uri(base_uri: &Uri, path: &str, query: Option<&str>) -> Result<Uri, Error> {
+    let base_path = base_uri.path().trim_end_matches('/');
+    let path = path.trim_start_matches('/');
+    let mut path_and_query = format!("{base_path}/{path}");
+
+    let queries = [base_uri.query(), query];
+    let mut queries = queries.iter().flatten().filter(|query| !query.is_empty());
+    if let Some(first) = queries.next() {
+        path_and_query.push('?');
+        path_and_query.push_str(first);
+        for query in queries {
+            path_and_query.push('&');
+            path_and_query.push_str(query);
+        }
+    }
+
+    let mut parts = base_uri.clone().into_parts();
+    parts.path_and_query = Some(path_and_query.try_into()?);
+    Ok(Uri::from_parts(parts)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn compose(base: &str, path: &str, query: Option<&str>) -> String {
+        compose_uri(&base.parse().unwrap(), path, query)
+            .unwrap()
+            .to_string()
+    }
+
+    #[test]
+    fn slashes() {
+        for (base, path, expected) in [
+            ("https://example.org", "/file", "https://example.org/file"),
+            ("https://example.org/", "/file", "https://example.org/file"),
+            (
+                "https://example.org/pub",
+                "/file",
+                "https://example.org/pub/file",
+            ),
+            (
+                "https://example.org/pub/",
+                "/file",
+                "https://example.org/pub/file",
+            ),
+            (
+                "https://example.org/pub/",
+                "/dir/",
+                "https://example.org/pub/dir/",
+            ),
+            ("https://example.org/pub/", "/", "https://example.org/pub/"),
+            (
+                "https://example.org/pub",
+                "file",
+                "https://example.org/pub/file",
+            ),
+        ] {
+            assert_eq!(compose(base, path, None), expected, "{base} {path}");
+        }
+    }
+
+    #[test]
+    fn queries() {
+        for (base, query, expected) in [
+            (
+                "https://example.org/",
+                Some("a=1"),
+                "https://example.org/f?a=1",
+            ),
+            (
+                "https://example.org/?key=x",
+                None,
+                "https://example.org/f?key=x",
+            ),
+            (
+                "https://example.org/?key=x",
+                Some("a=1&b=2"),
+                "https://example.org/f?key=x&a=1&b=2",
+            ),
+            ("https://example.org/?", Some(""), "https://example.org/f"),
+        ] {
+            assert_eq!(compose(base, "/f", query), expected, "{base} {query:?}");
+        }
+    }
+
+    #[test]
+    fn percent_encoding() {
+        assert_eq!(
+            compose(
+                "https://example.org/my%20data/",
+                "/file%2Fname%3F.txt",
+                Some("q=a%26b")
+            ),
+            "https://example.org/my%20data/file%2Fname%3F.txt?q=a%26b"
+        );
+    }
+
+    #[test]
+    fn absolute_uri() {
+        assert!(parse_absolute_uri("https://example.org/pub").is_ok());
+        for s in ["/pub", "example.org", "//example.org/pub"] {
+            assert!(
+                matches!(parse_absolute_uri(s), Err(AbsoluteUriError::NotAbsolute(_))),
+                "{s}"
+            );
+        }
+        assert!(matches!(
+            parse_absolute_uri("https://exa mple.org"),
+            Err(AbsoluteUriError::InvalidUri { .. })
+        ));
+    }
 }
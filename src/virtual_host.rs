@@ -0,0 +1,200 @@
+use crate::mirror::Mirror;
+use crate::routes::{RouteConfig, Routes, RoutesConfigError};
+
+use hyper::HeaderMap;
+use serde::Deserialize;
+use std::collections::HashMap;
+use thiserror::Error;
+
+#[derive(Deserialize, Debug, Default)]
+#[serde(transparent)]
+struct ResponseHeaders(#[serde(with = "http_serde::header_map")] HeaderMap);
+
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct VirtualHostConfig {
+    /// Host names to match against "Host" header or SNI, "*.example.org" wildcards are supported
+    hosts: Vec<String>,
+    /// Replaces top-level response_headers if specified
+    #[serde(default)]
+    response_headers: Option<ResponseHeaders>,
+    continents: HashMap<String, Vec<String>>,
+    #[serde(default)]
+    routes: Vec<RouteConfig>,
+}
+
+/// Response headers and routes of a single virtual host
+#[derive(Debug)]
+pub struct Site {
+    pub response_headers: HeaderMap,
+    pub routes: Routes,
+}
+
+/// Sites selected by host name, the default site is used for unknown hosts
+#[derive(Debug)]
+pub struct VirtualHosts {
+    default: Site,
+    sites: Vec<Site>,
+    by_host: HashMap<String, usize>,
+}
+
+impl VirtualHosts {
+    pub fn from_config(
+        default: Site,
+        mirrors: &HashMap<String, Mirror>,
+        virtual_hosts: Vec<VirtualHostConfig>,
+    ) -> Result<Self, VirtualHostsConfigError> {
+        let mut sites = Vec::with_capacity(virtual_hosts.len());
+        let mut by_host = HashMap::new();
+        for (index, config) in virtual_hosts.into_iter().enumerate() {
+            if config.hosts.is_empty() {
+                return Err(VirtualHostsConfigError::NoHosts { index });
+            }
+            for host in config.hosts {
+                let host = Self::strip_port(&host).to_ascii_lowercase();
+                if by_host.insert(host.clone(), index).is_some() {
+                    return Err(VirtualHostsConfigError::DuplicateHost(host));
+                }
+            }
+            let routes = Routes::from_config(mirrors, &config.continents, &config.routes)
+                .map_err(|error| VirtualHostsConfigError::Routes { index, error })?;
+            sites.push(Site {
+                response_headers: config
+                    .response_headers
+                    .map_or_else(|| default.response_headers.clone(), |headers| headers.0),
+                routes,
+            });
+        }
+        Ok(Self {
+            default,
+            sites,
+            by_host,
+        })
+    }
+
+    /// Site for the host name, which could include a port
+    pub fn get(&self, host: Option<&str>) -> &Site {
+        host.map(|host| Self::strip_port(host).to_ascii_lowercase())
+            .and_then(|host| {
+                self.by_host.get(&host).or_else(|| {
+                    let (_, parent) = host.split_once('.')?;
+                    self.by_host.get(&format!("*.{parent}"))
+                })
+            })
+            .map_or(&self.default, |&index| &self.sites[index])
+    }
+
+    fn strip_port(host: &str) -> &str {
+        // IPv6 literal like "[::1]:8080"
+        if let Some(rest) = host.strip_prefix('[') {
+            return rest.split_once(']').map_or(host, |(ip, _)| ip);
+        }
+        host.rsplit_once(':').map_or(host, |(host, _)| host)
+    }
+
+    pub fn all_mirrors(&self) -> &[Mirror] {
+        self.default.routes.all_mirrors()
+    }
+}
+
+#[derive(Error, Debug, PartialEq, Eq)]
+pub enum VirtualHostsConfigError {
+    #[error("virtual_hosts[{index}]: hosts must not be empty")]
+    NoHosts { index: usize },
+    #[error(r#"host "{0}" is specified for multiple virtual hosts"#)]
+    DuplicateHost(String),
+    #[error("virtual_hosts[{index}]: {error}")]
+    Routes {
+        index: usize,
+        error: RoutesConfigError,
+    },
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::geo::Continent;
+
+    #[derive(Debug, Deserialize)]
+    struct VirtualHostsConfig {
+        #[serde(with = "http_serde::header_map")]
+        response_headers: HeaderMap,
+        #[serde(deserialize_with = "crate::mirror::deserialize_mirrors")]
+        mirrors: HashMap<String, Mirror>,
+        continents: HashMap<String, Vec<String>>,
+        virtual_hosts: Vec<VirtualHostConfig>,
+    }
+
+    fn virtual_hosts_from_str(s: &str) -> Result<VirtualHosts, VirtualHostsConfigError> {
+        let config: VirtualHostsConfig = toml::from_str(s).unwrap();
+        let default = Site {
+            response_headers: config.response_headers,
+            routes: Routes::from_config(&config.mirrors, &config.continents, &[]).unwrap(),
+        };
+        VirtualHosts::from_config(default, &config.mirrors, config.virtual_hosts)
+    }
+
+    const CONFIG: &str = r#"
+        response_headers = { X-Site = "default" }
+
+        [mirrors]
+        a = { upstream = "http://a.example.com", healthcheck = "http://a.example.com/ping" }
+        b = { upstream = "http://b.example.com", healthcheck = "http://b.example.com/ping" }
+
+        [continents]
+        default = ["a"]
+
+        [[virtual_hosts]]
+        hosts = ["data.example.org", "[::1]"]
+        response_headers = { X-Site = "data" }
+        continents = { default = ["b"] }
+
+        [[virtual_hosts]]
+        hosts = ["*.software.example.org"]
+        continents = { default = ["a", "b"] }
+        "#;
+
+    #[test]
+    fn select_virtual_host() {
+        let virtual_hosts = virtual_hosts_from_str(CONFIG).unwrap();
+        let site_header = |host| {
+            virtual_hosts.get(host).response_headers["X-Site"]
+                .to_str()
+                .unwrap()
+                .to_owned()
+        };
+        assert_eq!(site_header(None), "default");
+        assert_eq!(site_header(Some("example.org")), "default");
+        assert_eq!(site_header(Some("data.example.org")), "data");
+        assert_eq!(site_header(Some("DATA.example.org:8080")), "data");
+        assert_eq!(site_header(Some("[::1]:8080")), "data");
+        assert_eq!(site_header(Some("www.software.example.org")), "default");
+        assert_eq!(site_header(Some("software.example.org")), "default");
+
+        let site = virtual_hosts.get(Some("data.example.org"));
+        assert_eq!(
+            site.routes.get("/").get(Continent::Default)[0]
+                .upstream
+                .to_string(),
+            "http://b.example.com/"
+        );
+        assert_eq!(
+            virtual_hosts
+                .get(Some("www.software.example.org"))
+                .routes
+                .get("/")
+                .get(Continent::Default)
+                .len(),
+            2
+        );
+    }
+
+    #[test]
+    fn duplicate_host() {
+        let s = CONFIG.replace("*.software.example.org", "data.example.org");
+        assert_eq!(
+            virtual_hosts_from_str(&s).unwrap_err(),
+            VirtualHostsConfigError::DuplicateHost("data.example.org".to_owned())
+        );
+    }
+}